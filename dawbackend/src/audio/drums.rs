@@ -0,0 +1,290 @@
+use std::f32::consts::TAU;
+
+use dawlib::{DrumKitParametersDto, DrumPieceDto, InstrumentDto, MidiKey};
+
+use super::{boxed, instrument::Instrument, AudioError, Frame, SoundNode};
+
+const HAT_CHOKE_GROUP: usize = 0;
+const BURST_SPACING: f32 = 0.01;
+const BURST_DECAY: f32 = 0.003;
+// After this many time constants a decay is below -60 dB.
+const DECAY_TAIL: f32 = 7.0;
+
+pub fn kick_factory(
+    instrument: &InstrumentDto,
+    sample_rate: usize,
+) -> Result<Box<dyn Instrument>, AudioError> {
+    let piece = if instrument.parameters.is_null() {
+        DrumPieceDto::default()
+    } else {
+        instrument.parameters::<DrumPieceDto>().map_err(|error| {
+            AudioError::InvalidParameters(
+                instrument.instrument_type().to_string(),
+                error.to_string(),
+            )
+        })?
+    };
+
+    Ok(Box::new(KickInstrument { piece, sample_rate }))
+}
+
+pub fn kit_factory(
+    instrument: &InstrumentDto,
+    sample_rate: usize,
+) -> Result<Box<dyn Instrument>, AudioError> {
+    let kit = if instrument.parameters.is_null() {
+        DrumKitParametersDto::default()
+    } else {
+        instrument
+            .parameters::<DrumKitParametersDto>()
+            .map_err(|error| {
+                AudioError::InvalidParameters(
+                    instrument.instrument_type().to_string(),
+                    error.to_string(),
+                )
+            })?
+    };
+
+    Ok(Box::new(DrumKitInstrument { kit, sample_rate }))
+}
+
+/// One-shot like the pieces of the kit, the hit decays on its own whatever the note length.
+struct KickInstrument {
+    piece: DrumPieceDto,
+    sample_rate: usize,
+}
+
+impl Instrument for KickInstrument {
+    fn voice(&self, _key: MidiKey, _gate: usize) -> Box<dyn SoundNode> {
+        boxed(DrumNode::new(&self.piece, self.sample_rate))
+    }
+}
+
+struct DrumKitInstrument {
+    kit: DrumKitParametersDto,
+    sample_rate: usize,
+}
+
+impl DrumKitInstrument {
+    fn piece(&self, key: MidiKey) -> Option<&DrumPieceDto> {
+        match key {
+            MidiKey::C1 => Some(&self.kit.kick),
+            MidiKey::Db1 => Some(&self.kit.rimshot),
+            MidiKey::D1 => Some(&self.kit.snare),
+            MidiKey::Eb1 => Some(&self.kit.clap),
+            MidiKey::Gb1 => Some(&self.kit.closed_hat),
+            MidiKey::Bb2 => Some(&self.kit.open_hat),
+            MidiKey::F1 => Some(&self.kit.low_tom),
+            MidiKey::A2 => Some(&self.kit.mid_tom),
+            MidiKey::C2 => Some(&self.kit.high_tom),
+            _ => None,
+        }
+    }
+}
+
+impl Instrument for DrumKitInstrument {
+    fn voice(&self, key: MidiKey, _gate: usize) -> Box<dyn SoundNode> {
+        match self.piece(key) {
+            Some(piece) => boxed(DrumNode::new(piece, self.sample_rate)),
+            None => boxed(Silence),
+        }
+    }
+
+    fn choke_group(&self, key: MidiKey) -> Option<usize> {
+        matches!(key, MidiKey::Gb1 | MidiKey::Bb2).then_some(HAT_CHOKE_GROUP)
+    }
+}
+
+struct DrumNode {
+    piece: DrumPieceDto,
+    sample_rate: f32,
+    current_sample: usize,
+    length: usize,
+    phase: f32,
+    noise: Noise,
+    highpass: f32,
+    highpass_input: f32,
+    highpass_state: f32,
+}
+
+impl DrumNode {
+    fn new(piece: &DrumPieceDto, sample_rate: usize) -> Self {
+        let bursts = piece.bursts.max(1);
+        let noise_length = (bursts - 1) as f32 * BURST_SPACING + piece.noise_decay * DECAY_TAIL;
+        let body_length = piece.body_decay * DECAY_TAIL;
+        let rc = 1.0 / (TAU * piece.noise_cutoff.max(1.0));
+
+        Self {
+            piece: DrumPieceDto {
+                bursts,
+                ..piece.clone()
+            },
+            sample_rate: sample_rate as f32,
+            current_sample: 0,
+            length: (noise_length.max(body_length) * sample_rate as f32) as usize,
+            phase: 0.0,
+            noise: Noise::default(),
+            highpass: rc / (rc + 1.0 / sample_rate as f32),
+            highpass_input: 0.0,
+            highpass_state: 0.0,
+        }
+    }
+
+    fn body(&mut self, time: f32) -> f32 {
+        if self.piece.body == 0.0 {
+            return 0.0;
+        }
+
+        let sweep = if self.piece.sweep_time > 0.0 {
+            (self.piece.sweep - 1.0) * (-time / self.piece.sweep_time).exp()
+        } else {
+            0.0
+        };
+        let frequency = self.piece.tone * (1.0 + sweep);
+        let value = (self.phase * TAU).sin();
+        self.phase = (self.phase + frequency / self.sample_rate).fract();
+
+        value * self.piece.body * decay(time, self.piece.body_decay)
+    }
+
+    fn noise(&mut self, time: f32) -> f32 {
+        if self.piece.noise == 0.0 {
+            return 0.0;
+        }
+
+        let input = self.noise.next();
+        self.highpass_state = self.highpass * (self.highpass_state + input - self.highpass_input);
+        self.highpass_input = input;
+
+        let last_burst = (self.piece.bursts - 1) as f32 * BURST_SPACING;
+        let level = if time < last_burst {
+            decay(time % BURST_SPACING, BURST_DECAY)
+        } else {
+            decay(time - last_burst, self.piece.noise_decay)
+        };
+
+        self.highpass_state * self.piece.noise * level
+    }
+}
+
+impl SoundNode for DrumNode {
+    fn next_frame(&mut self) -> Option<Frame> {
+        if self.ended() {
+            return None;
+        }
+
+        let time = self.current_sample as f32 / self.sample_rate;
+        let value = self.body(time) + self.noise(time);
+        self.current_sample += 1;
+        Some(Frame::mono(value))
+    }
+
+    fn ended(&self) -> bool {
+        self.current_sample >= self.length
+    }
+}
+
+fn decay(time: f32, time_constant: f32) -> f32 {
+    if time_constant > 0.0 {
+        (-time / time_constant).exp()
+    } else {
+        0.0
+    }
+}
+
+/// Xorshift white noise, cheap and the same on every run.
+struct Noise {
+    state: u32,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self { state: 0x9E37_79B9 }
+    }
+}
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+struct Silence;
+
+impl SoundNode for Silence {
+    fn next_frame(&mut self) -> Option<Frame> {
+        None
+    }
+
+    fn ended(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(piece: &DrumPieceDto) -> Vec<f32> {
+        let mut node = DrumNode::new(piece, 44100);
+        std::iter::from_fn(|| node.next_frame())
+            .map(|frame| frame.left)
+            .collect()
+    }
+
+    #[test]
+    fn test_open_hat_rings_longer_than_closed_hat() {
+        // given
+        let kit = DrumKitParametersDto::default();
+
+        // when
+        let closed = render(&kit.closed_hat);
+        let open = render(&kit.open_hat);
+
+        // then
+        assert!(open.len() > closed.len() * 5);
+        assert!(closed.iter().any(|value| value.abs() > 0.1));
+    }
+
+    #[test]
+    fn test_kick_ignores_note_length() {
+        // given
+        let kick = KickInstrument {
+            piece: DrumPieceDto::default(),
+            sample_rate: 44100,
+        };
+
+        let length = |gate| {
+            let mut voice = kick.voice(MidiKey::C1, gate);
+            std::iter::from_fn(|| voice.next_frame()).count()
+        };
+
+        // when
+        let (short, long) = (length(10), length(44100));
+
+        // then
+        assert!(short > 4410);
+        assert_eq!(short, long);
+    }
+
+    #[test]
+    fn test_hats_share_choke_group() {
+        // given
+        let instrument = DrumKitInstrument {
+            kit: DrumKitParametersDto::default(),
+            sample_rate: 44100,
+        };
+
+        // when
+        let closed = instrument.choke_group(MidiKey::Gb1);
+        let open = instrument.choke_group(MidiKey::Bb2);
+
+        // then
+        assert_eq!(closed, Some(HAT_CHOKE_GROUP));
+        assert_eq!(open, closed);
+        assert_eq!(instrument.choke_group(MidiKey::D1), None);
+    }
+}
@@ -0,0 +1,209 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use dawlib::{AutomationParameter, DelayDto, FilterMode, NoteFeel};
+
+use super::{DelayLine, Effect, Mix};
+use crate::audio::{filter::Biquad, timeline::Timeline, Frame};
+
+const MAX_FEEDBACK: f32 = 0.95;
+const SILENCE: f32 = 0.001;
+const MAX_TIME_SECONDS: f64 = 4.0;
+
+pub struct Delay {
+    line: DelayLine,
+    beats: f64,
+    beats_offset: f64,
+    samples_per_beat: f64,
+    length: usize,
+    max_length: usize,
+    feedback: f32,
+    filters: Vec<Biquad>,
+    ping_pong: bool,
+    mix: Mix,
+    tail: usize,
+}
+
+impl Delay {
+    pub fn new(delay: &DelayDto, timeline: &Timeline, sample_rate: usize) -> Self {
+        let feel = match delay.feel {
+            NoteFeel::Straight => 1.0,
+            NoteFeel::Dotted => 1.5,
+            NoteFeel::Triplet => 2.0 / 3.0,
+        };
+        let beats = 4.0 / delay.division.max(1) as f64 * feel;
+        let length = |samples_per_beat: f64| ((beats * samples_per_beat).round() as usize).max(1);
+        // Room for the echo at the slowest tempo of the song, it follows the tempo map.
+        let cap = (MAX_TIME_SECONDS * sample_rate as f64) as usize;
+        let longest = length(timeline.longest_beat()).min(cap);
+
+        let feedback = delay.feedback.clamp(0.0, MAX_FEEDBACK);
+        let repeats = if feedback > 0.0 {
+            (SILENCE.ln() / feedback.ln()).ceil() as usize
+        } else {
+            0
+        };
+
+        let filters = [
+            (FilterMode::Lowpass, delay.lowpass),
+            (FilterMode::Highpass, delay.highpass),
+        ]
+        .into_iter()
+        .filter_map(|(mode, cutoff)| {
+            cutoff.map(|cutoff| Biquad::new(mode, cutoff, FRAC_1_SQRT_2, 0.0, sample_rate))
+        })
+        .collect();
+
+        let samples_per_beat = timeline.samples_per_beat_at(0);
+        Self {
+            line: DelayLine::new(longest),
+            beats,
+            beats_offset: 0.0,
+            samples_per_beat,
+            length: length(samples_per_beat).min(longest),
+            max_length: longest,
+            feedback,
+            filters,
+            ping_pong: delay.ping_pong,
+            mix: Mix::new(delay.mix),
+            tail: (repeats + 1) * longest,
+        }
+    }
+
+    /// Times longer than the line, sized for the payload and at most four seconds, are cut to it.
+    fn update_length(&mut self) {
+        let beats = (self.beats + self.beats_offset).max(0.0);
+        self.length = ((beats * self.samples_per_beat).round() as usize).clamp(1, self.max_length);
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, frame: Frame) -> Frame {
+        let delayed = self.line.read_at(self.length);
+        let repeat = self
+            .filters
+            .iter_mut()
+            .fold(delayed, |signal, filter| filter.process(signal))
+            * self.feedback;
+
+        if self.ping_pong {
+            // Everything enters on the left and crosses over on every repeat.
+            let input = (frame.left + frame.right) * 0.5;
+            self.line
+                .write(Frame::new(input + repeat.right, repeat.left));
+        } else {
+            self.line.write(frame + repeat);
+        }
+
+        self.mix.blend(frame, delayed)
+    }
+
+    fn tail(&self) -> usize {
+        self.tail
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        match parameter {
+            AutomationParameter::DelayMix => self.mix.set(value),
+            AutomationParameter::DelayTime => {
+                self.beats = value.max(0.0) as f64;
+                self.update_length();
+            }
+            _ => {}
+        }
+    }
+
+    fn modulate(&mut self, parameter: AutomationParameter, offset: f32) {
+        match parameter {
+            AutomationParameter::DelayMix => self.mix.modulate(offset),
+            AutomationParameter::DelayTime => {
+                self.beats_offset = offset as f64;
+                self.update_length();
+            }
+            _ => {}
+        }
+    }
+
+    fn follow_tempo(&mut self, samples_per_beat: f64) {
+        self.samples_per_beat = samples_per_beat;
+        self.update_length();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn delay(feel: NoteFeel, ping_pong: bool) -> Delay {
+        Delay::new(
+            &DelayDto {
+                division: 8,
+                feel,
+                feedback: 0.5,
+                lowpass: None,
+                highpass: None,
+                ping_pong,
+                mix: 1.0,
+            },
+            &Timeline::new(44100, 120.0, &[], 96),
+            44100,
+        )
+    }
+
+    fn impulse_response(delay: &mut Delay, length: usize) -> Vec<Frame> {
+        std::iter::once(Frame::mono(1.0))
+            .chain(std::iter::repeat(Frame::default()))
+            .take(length)
+            .map(|frame| delay.process(frame))
+            .collect()
+    }
+
+    #[test]
+    fn test_dotted_eighth_follows_tempo() {
+        // given
+        let mut delay = delay(NoteFeel::Dotted, false);
+
+        // when
+        let response = impulse_response(&mut delay, 40000);
+
+        // then
+        assert_eq!(response[16538], Frame::mono(1.0));
+        assert_eq!(response[2 * 16538], Frame::mono(0.5));
+        assert_eq!(response[16537], Frame::default());
+    }
+
+    #[test]
+    fn test_slow_delays_are_capped() {
+        // given
+        let dto = DelayDto {
+            division: 1,
+            feel: NoteFeel::Dotted,
+            feedback: 0.5,
+            lowpass: None,
+            highpass: None,
+            ping_pong: false,
+            mix: 1.0,
+        };
+
+        // when
+        let mut delay = Delay::new(&dto, &Timeline::new(44100, 0.01, &[], 96), 44100);
+        delay.follow_tempo(1e9);
+
+        // then
+        assert_eq!(delay.length, 4 * 44100);
+        assert!(delay.tail <= 20 * 4 * 44100);
+    }
+
+    #[test]
+    fn test_ping_pong_alternates_channels() {
+        // given
+        let mut delay = delay(NoteFeel::Straight, true);
+
+        // when
+        let response = impulse_response(&mut delay, 3 * 11025 + 1);
+
+        // then
+        assert_eq!(response[11025], Frame::new(1.0, 0.0));
+        assert_eq!(response[2 * 11025], Frame::new(0.0, 0.5));
+        assert_eq!(response[3 * 11025], Frame::new(0.25, 0.0));
+    }
+}
@@ -0,0 +1,287 @@
+use dawlib::{AutomationParameter, DistortionDto, DistortionShape, FilterMode};
+
+use super::{Effect, Mix};
+use crate::audio::{filter::Biquad, Frame};
+
+const MAX_OVERSAMPLING: usize = 8;
+// Anti-aliasing cutoff as a fraction of the engine sample rate, just below its Nyquist.
+const PASSBAND: f32 = 0.45;
+// Q of the two sections of a fourth order Butterworth lowpass.
+const BUTTERWORTH_Q: [f32; 2] = [0.541_196_1, 1.306_563];
+const DC_BLOCKER_POLE: f32 = 0.995;
+
+pub struct Distortion {
+    shaper: Shaper,
+    oversampling: usize,
+    upsampling: Vec<Biquad>,
+    downsampling: Vec<Biquad>,
+    dc_blocker: DcBlocker,
+    mix: Mix,
+}
+
+enum Shaper {
+    Drive {
+        gain: f32,
+    },
+    Waveshaper {
+        gain: f32,
+        bias: f32,
+    },
+    Foldback {
+        gain: f32,
+        threshold: f32,
+    },
+    Bitcrusher {
+        step: f32,
+        hold: usize,
+        counter: usize,
+        held: Frame,
+    },
+}
+
+impl Distortion {
+    pub fn new(distortion: &DistortionDto, sample_rate: usize) -> Self {
+        let oversampling = distortion
+            .oversampling
+            .clamp(1, MAX_OVERSAMPLING)
+            .next_power_of_two();
+        let gain = |drive: f32| 10f32.powf(drive / 20.0);
+
+        let shaper = match distortion.shape {
+            DistortionShape::Drive { drive } => Shaper::Drive { gain: gain(drive) },
+            DistortionShape::Waveshaper { drive, asymmetry } => Shaper::Waveshaper {
+                gain: gain(drive),
+                bias: asymmetry.clamp(-1.0, 1.0),
+            },
+            DistortionShape::Foldback { drive, threshold } => Shaper::Foldback {
+                gain: gain(drive),
+                threshold: threshold.max(0.01),
+            },
+            DistortionShape::Bitcrusher { bits, downsample } => Shaper::Bitcrusher {
+                step: 1.0 / (1u32 << (bits.clamp(1, 24) - 1)) as f32,
+                // The hold is counted at the oversampled rate.
+                hold: downsample.max(1) * oversampling,
+                counter: 0,
+                held: Frame::default(),
+            },
+        };
+
+        let lowpass = || {
+            if oversampling == 1 {
+                return Vec::new();
+            }
+            BUTTERWORTH_Q
+                .iter()
+                .map(|q| {
+                    Biquad::new(
+                        FilterMode::Lowpass,
+                        PASSBAND * sample_rate as f32,
+                        *q,
+                        0.0,
+                        sample_rate * oversampling,
+                    )
+                })
+                .collect()
+        };
+
+        Self {
+            shaper,
+            oversampling,
+            upsampling: lowpass(),
+            downsampling: lowpass(),
+            dc_blocker: DcBlocker::default(),
+            mix: Mix::new(distortion.mix),
+        }
+    }
+}
+
+impl Effect for Distortion {
+    fn process(&mut self, frame: Frame) -> Frame {
+        let mut wet = Frame::default();
+        for index in 0..self.oversampling {
+            // Zero stuffing, the gain makes up for the energy spread over the inserted samples.
+            let input = if index == 0 {
+                frame * self.oversampling as f32
+            } else {
+                Frame::default()
+            };
+            let input = self
+                .upsampling
+                .iter_mut()
+                .fold(input, |signal, filter| filter.process(signal));
+            let shaped = self.shaper.process(input);
+            let output = self
+                .downsampling
+                .iter_mut()
+                .fold(shaped, |signal, filter| filter.process(signal));
+            if index == 0 {
+                wet = output;
+            }
+        }
+
+        let wet = self.dc_blocker.process(wet);
+        self.mix.blend(frame, wet)
+    }
+
+    fn tail(&self) -> usize {
+        0
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        if parameter == AutomationParameter::DistortionMix {
+            self.mix.set(value);
+        }
+    }
+
+    fn modulate(&mut self, parameter: AutomationParameter, offset: f32) {
+        if parameter == AutomationParameter::DistortionMix {
+            self.mix.modulate(offset);
+        }
+    }
+}
+
+impl Shaper {
+    fn process(&mut self, frame: Frame) -> Frame {
+        match self {
+            Shaper::Drive { gain } => {
+                let gain = *gain;
+                map(frame, |value| (value * gain).tanh())
+            }
+            Shaper::Waveshaper { gain, bias } => {
+                let (gain, bias) = (*gain, *bias);
+                // Shifting the operating point makes the curve clip harder on one side,
+                // the constant offset it leaves is removed by the DC blocker.
+                map(frame, |value| (value * gain + bias).tanh() - bias.tanh())
+            }
+            Shaper::Foldback { gain, threshold } => {
+                let (gain, threshold) = (*gain, *threshold);
+                map(frame, |value| fold(value * gain, threshold))
+            }
+            Shaper::Bitcrusher {
+                step,
+                hold,
+                counter,
+                held,
+            } => {
+                if *counter == 0 {
+                    let step = *step;
+                    *held = map(frame, |value| {
+                        ((value / step).round() * step).clamp(-1.0, 1.0)
+                    });
+                }
+                *counter = (*counter + 1) % *hold;
+                *held
+            }
+        }
+    }
+}
+
+fn map(frame: Frame, function: impl Fn(f32) -> f32) -> Frame {
+    Frame::new(function(frame.left), function(frame.right))
+}
+
+/// Reflects the signal off `threshold` and `-threshold` until it fits between them.
+fn fold(value: f32, threshold: f32) -> f32 {
+    ((value - threshold).rem_euclid(4.0 * threshold) - 2.0 * threshold).abs() - threshold
+}
+
+#[derive(Default)]
+struct DcBlocker {
+    input: Frame,
+    output: Frame,
+}
+
+impl DcBlocker {
+    fn process(&mut self, input: Frame) -> Frame {
+        let output = Frame::new(
+            input.left - self.input.left + DC_BLOCKER_POLE * self.output.left,
+            input.right - self.input.right + DC_BLOCKER_POLE * self.output.right,
+        );
+        self.input = input;
+        self.output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    fn distortion(shape: DistortionShape, oversampling: usize) -> Distortion {
+        Distortion::new(
+            &DistortionDto {
+                shape,
+                oversampling,
+                mix: 1.0,
+            },
+            44100,
+        )
+    }
+
+    /// Level of the output at `frequency`, measured on the second half of a one second sine.
+    fn level_at(effect: &mut Distortion, input: f32, frequency: f32) -> f32 {
+        let output = (0..44100)
+            .map(|sample| {
+                let value = (sample as f32 * input / 44100.0 * TAU).sin();
+                effect.process(Frame::mono(value)).left
+            })
+            .collect::<Vec<_>>();
+        let (real, imaginary) = output[22050..].iter().enumerate().fold(
+            (0.0, 0.0),
+            |(real, imaginary), (sample, value)| {
+                let phase = sample as f32 * frequency / 44100.0 * TAU;
+                (real + value * phase.cos(), imaginary + value * phase.sin())
+            },
+        );
+        (real * real + imaginary * imaginary).sqrt() / 11025.0
+    }
+
+    #[test]
+    fn test_fold_reflects_above_threshold() {
+        // given
+        let threshold = 0.5;
+
+        // when
+        let values = [0.25, 0.75, 1.25, -0.75].map(|value| fold(value, threshold));
+
+        // then
+        assert_eq!(values, [0.25, 0.25, -0.25, -0.25]);
+    }
+
+    #[test]
+    fn test_bitcrusher_quantizes_and_holds() {
+        // given
+        let mut crusher = distortion(
+            DistortionShape::Bitcrusher {
+                bits: 2,
+                downsample: 2,
+            },
+            1,
+        );
+
+        // when
+        let output =
+            [0.3, 0.9, -0.8, 0.1].map(|value| crusher.shaper.process(Frame::mono(value)).left);
+
+        // then
+        assert_eq!(output, [0.5, 0.5, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_oversampling_reduces_aliasing() {
+        // given
+        let drive = DistortionShape::Drive { drive: 24.0 };
+        let mut plain = distortion(drive, 1);
+        let mut oversampled = distortion(drive, 8);
+
+        // when
+        // The 5th harmonic of 5 kHz lands at 25 kHz and folds back to 19.1 kHz.
+        let plain = level_at(&mut plain, 5000.0, 19100.0);
+        let oversampled = level_at(&mut oversampled, 5000.0, 19100.0);
+
+        // then
+        assert!(oversampled < plain * 0.5);
+    }
+}
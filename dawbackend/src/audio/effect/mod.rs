@@ -0,0 +1,252 @@
+use dawlib::{AutomationParameter, EffectDto};
+
+use self::{delay::Delay, distortion::Distortion, modulation::Modulation, reverb::Reverb};
+use super::{timeline::Timeline, Frame, SoundNode};
+
+pub mod delay;
+pub mod distortion;
+pub mod modulation;
+pub mod reverb;
+
+/// Processes the summed voices of an instrument, once per frame.
+pub trait Effect: Send {
+    fn process(&mut self, frame: Frame) -> Frame;
+
+    /// How many samples the effect keeps sounding after its input falls silent.
+    fn tail(&self) -> usize;
+
+    /// Sets an automated parameter, effects ignore the parameters that are not theirs.
+    fn automate(&mut self, _parameter: AutomationParameter, _value: f32) {}
+
+    /// Offsets a parameter from its automated value, until the next call for that parameter.
+    fn modulate(&mut self, _parameter: AutomationParameter, _offset: f32) {}
+
+    /// Called whenever the tempo of the song changes, for the effects synced to it.
+    fn follow_tempo(&mut self, _samples_per_beat: f64) {}
+}
+
+impl Effect for Vec<Box<dyn Effect>> {
+    fn process(&mut self, frame: Frame) -> Frame {
+        self.iter_mut()
+            .fold(frame, |frame, effect| effect.process(frame))
+    }
+
+    fn tail(&self) -> usize {
+        self.iter().map(|effect| effect.tail()).sum()
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        for effect in self {
+            effect.automate(parameter, value);
+        }
+    }
+
+    fn modulate(&mut self, parameter: AutomationParameter, offset: f32) {
+        for effect in self {
+            effect.modulate(parameter, offset);
+        }
+    }
+
+    fn follow_tempo(&mut self, samples_per_beat: f64) {
+        for effect in self {
+            effect.follow_tempo(samples_per_beat);
+        }
+    }
+}
+
+/// Runs a sound through an effect, and keeps going for the effect tail once the sound has ended.
+pub struct EffectNode<T: SoundNode, E: Effect> {
+    node: T,
+    effect: E,
+    idle_samples: usize,
+}
+
+impl<T: SoundNode, E: Effect> EffectNode<T, E> {
+    pub fn new(node: T, effect: E) -> Self {
+        Self {
+            node,
+            effect,
+            idle_samples: usize::MAX,
+        }
+    }
+
+    pub fn node(&self) -> &T {
+        &self.node
+    }
+
+    pub fn node_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+
+    pub fn effect_mut(&mut self) -> &mut E {
+        &mut self.effect
+    }
+}
+
+impl<T: SoundNode, E: Effect> SoundNode for EffectNode<T, E> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        if self.ended() {
+            return None;
+        }
+
+        let playing = !self.node.ended();
+        let frame = self.node.next_frame().unwrap_or_default();
+        if playing {
+            self.idle_samples = 0;
+        } else {
+            self.idle_samples = self.idle_samples.saturating_add(1);
+        }
+
+        Some(self.effect.process(frame))
+    }
+
+    fn ended(&self) -> bool {
+        self.node.ended() && self.idle_samples >= self.effect.tail()
+    }
+}
+
+/// Builds the effects of an instrument or bus, in the order they process the signal.
+pub fn chain(
+    effects: &[EffectDto],
+    timeline: &Timeline,
+    sample_rate: usize,
+) -> Vec<Box<dyn Effect>> {
+    effects
+        .iter()
+        .map(|effect| -> Box<dyn Effect> {
+            match effect {
+                EffectDto::Distortion(distortion) => {
+                    Box::new(Distortion::new(distortion, sample_rate))
+                }
+                EffectDto::Modulation(modulation) => {
+                    Box::new(Modulation::new(modulation, sample_rate))
+                }
+                EffectDto::Delay(delay) => Box::new(Delay::new(delay, timeline, sample_rate)),
+                EffectDto::Reverb(reverb) => Box::new(Reverb::new(reverb, sample_rate)),
+            }
+        })
+        .collect()
+}
+
+/// Balance between the dry and the processed signal, automated and modulated from 0 to 1.
+pub struct Mix {
+    value: f32,
+    offset: f32,
+}
+
+impl Mix {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value: value.clamp(0.0, 1.0),
+            offset: 0.0,
+        }
+    }
+
+    pub fn set(&mut self, value: f32) {
+        self.value = value.clamp(0.0, 1.0);
+    }
+
+    pub fn modulate(&mut self, offset: f32) {
+        self.offset = offset;
+    }
+
+    pub fn blend(&self, dry: Frame, wet: Frame) -> Frame {
+        let mix = (self.value + self.offset).clamp(0.0, 1.0);
+        dry * (1.0 - mix) + wet * mix
+    }
+}
+
+/// Ring buffer of frames, the building block of the delay based effects.
+pub struct DelayLine {
+    buffer: Vec<Frame>,
+    position: usize,
+}
+
+impl DelayLine {
+    pub fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![Frame::default(); length],
+            position: 0,
+        }
+    }
+
+    /// Stores the frame and returns the one stored `length` samples ago.
+    pub fn delay(&mut self, frame: Frame) -> Frame {
+        if self.buffer.is_empty() {
+            return frame;
+        }
+
+        let delayed = self.read();
+        self.write(frame);
+        delayed
+    }
+
+    /// The frame about to be overwritten by the next `write`, it needs a non-empty line.
+    pub fn read(&self) -> Frame {
+        self.buffer[self.position]
+    }
+
+    /// The frame written `delay` samples ago, from 1 up to the length of the line.
+    pub fn read_at(&self, delay: usize) -> Frame {
+        let length = self.buffer.len();
+        self.buffer[(self.position + length - delay.clamp(1, length)) % length]
+    }
+
+    pub fn write(&mut self, frame: Frame) {
+        self.buffer[self.position] = frame;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Click {
+        played: bool,
+    }
+
+    impl SoundNode for Click {
+        fn next_frame(&mut self) -> Option<Frame> {
+            self.played = true;
+            Some(Frame::mono(1.0))
+        }
+
+        fn ended(&self) -> bool {
+            self.played
+        }
+    }
+
+    struct Echo {
+        line: DelayLine,
+    }
+
+    impl Effect for Echo {
+        fn process(&mut self, frame: Frame) -> Frame {
+            self.line.delay(frame)
+        }
+
+        fn tail(&self) -> usize {
+            3
+        }
+    }
+
+    #[test]
+    fn test_effect_node_plays_tail_after_sound_ends() {
+        // given
+        let mut node = EffectNode::new(
+            Click { played: false },
+            Echo {
+                line: DelayLine::new(3),
+            },
+        );
+
+        // when
+        let output = std::iter::from_fn(|| node.next_frame())
+            .map(|frame| frame.left)
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(output, vec![0.0, 0.0, 0.0, 1.0]);
+    }
+}
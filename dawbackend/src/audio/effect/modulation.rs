@@ -0,0 +1,294 @@
+use std::f32::consts::PI;
+
+use dawlib::{AutomationParameter, LfoShape, ModulationDto};
+
+use super::{Effect, Mix};
+use crate::audio::{lfo::Lfo, Frame};
+
+// The right channel LFO runs a quarter cycle ahead, which widens the stereo image.
+const STEREO_PHASE: f32 = 0.25;
+const MAX_FEEDBACK: f32 = 0.95;
+
+const CHORUS_DELAY: f32 = 0.02;
+const CHORUS_SWING: f32 = 0.008;
+const FLANGER_DELAY: f32 = 0.001;
+const FLANGER_SWING: f32 = 0.004;
+const PHASER_LOWEST: f32 = 200.0;
+const PHASER_OCTAVES: f32 = 5.0;
+
+pub struct Modulation {
+    kind: Kind,
+    left: Lfo,
+    right: Lfo,
+    depth: f32,
+    feedback: f32,
+    mix: Mix,
+    sample_rate: f32,
+    tail: usize,
+}
+
+enum Kind {
+    /// Delay that sweeps between `delay` and `delay + swing` seconds.
+    Delay {
+        buffer: Vec<Frame>,
+        position: usize,
+        delay: f32,
+        swing: f32,
+    },
+    Phaser {
+        left: Vec<AllpassStage>,
+        right: Vec<AllpassStage>,
+        last: Frame,
+    },
+}
+
+impl Modulation {
+    pub fn new(modulation: &ModulationDto, sample_rate: usize) -> Self {
+        let (rate, depth, feedback, mix, kind) = match *modulation {
+            ModulationDto::Chorus { rate, depth, mix } => (
+                rate,
+                depth,
+                0.0,
+                mix,
+                delay_kind(CHORUS_DELAY, CHORUS_SWING, sample_rate),
+            ),
+            ModulationDto::Flanger {
+                rate,
+                depth,
+                feedback,
+                mix,
+            } => (
+                rate,
+                depth,
+                feedback,
+                mix,
+                delay_kind(FLANGER_DELAY, FLANGER_SWING, sample_rate),
+            ),
+            ModulationDto::Phaser {
+                rate,
+                depth,
+                stages,
+                feedback,
+                mix,
+            } => (
+                rate,
+                depth,
+                feedback,
+                mix,
+                Kind::Phaser {
+                    left: (0..stages.max(1))
+                        .map(|_| AllpassStage::default())
+                        .collect(),
+                    right: (0..stages.max(1))
+                        .map(|_| AllpassStage::default())
+                        .collect(),
+                    last: Frame::default(),
+                },
+            ),
+        };
+
+        let tail = match &kind {
+            Kind::Delay { buffer, .. } => buffer.len(),
+            Kind::Phaser { .. } => 0,
+        };
+
+        Self {
+            kind,
+            left: Lfo::new(LfoShape::Sine, rate, sample_rate, 0.0),
+            right: Lfo::new(LfoShape::Sine, rate, sample_rate, STEREO_PHASE),
+            depth: depth.clamp(0.0, 1.0),
+            feedback: feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK),
+            mix: Mix::new(mix),
+            sample_rate: sample_rate as f32,
+            // With feedback a flanger or phaser rings a little longer, a tenth of a second covers it.
+            tail: tail + if feedback != 0.0 { sample_rate / 10 } else { 0 },
+        }
+    }
+}
+
+fn delay_kind(delay: f32, swing: f32, sample_rate: usize) -> Kind {
+    Kind::Delay {
+        buffer: vec![Frame::default(); ((delay + swing) * sample_rate as f32) as usize + 2],
+        position: 0,
+        delay,
+        swing,
+    }
+}
+
+impl Effect for Modulation {
+    fn process(&mut self, frame: Frame) -> Frame {
+        // LFO mapped to 0..1 and scaled by the depth.
+        let left = (self.left.next_value() + 1.0) * 0.5 * self.depth;
+        let right = (self.right.next_value() + 1.0) * 0.5 * self.depth;
+
+        let wet = match &mut self.kind {
+            Kind::Delay {
+                buffer,
+                position,
+                delay,
+                swing,
+            } => {
+                let delayed = Frame::new(
+                    read(
+                        buffer,
+                        *position,
+                        (*delay + *swing * left) * self.sample_rate,
+                    )
+                    .left,
+                    read(
+                        buffer,
+                        *position,
+                        (*delay + *swing * right) * self.sample_rate,
+                    )
+                    .right,
+                );
+                buffer[*position] = frame + delayed * self.feedback;
+                *position = (*position + 1) % buffer.len();
+                delayed
+            }
+            Kind::Phaser {
+                left: left_stages,
+                right: right_stages,
+                last,
+            } => {
+                let coefficient = |sweep: f32| {
+                    let frequency = PHASER_LOWEST * (sweep * PHASER_OCTAVES).exp2();
+                    let tan = (PI * frequency / self.sample_rate).tan();
+                    (tan - 1.0) / (tan + 1.0)
+                };
+                let (left, right) = (coefficient(left), coefficient(right));
+
+                let input = frame + *last * self.feedback;
+                let output = Frame::new(
+                    left_stages
+                        .iter_mut()
+                        .fold(input.left, |signal, stage| stage.process(signal, left)),
+                    right_stages
+                        .iter_mut()
+                        .fold(input.right, |signal, stage| stage.process(signal, right)),
+                );
+                *last = output;
+                output
+            }
+        };
+
+        self.mix.blend(frame, wet)
+    }
+
+    fn tail(&self) -> usize {
+        self.tail
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        if parameter == AutomationParameter::ModulationMix {
+            self.mix.set(value);
+        }
+    }
+
+    fn modulate(&mut self, parameter: AutomationParameter, offset: f32) {
+        if parameter == AutomationParameter::ModulationMix {
+            self.mix.modulate(offset);
+        }
+    }
+}
+
+/// Reads `delay` samples behind the write position, interpolating between samples.
+fn read(buffer: &[Frame], position: usize, delay: f32) -> Frame {
+    let length = buffer.len();
+    let whole = (delay.floor() as usize).clamp(1, length - 2);
+    let fraction = (delay - whole as f32).clamp(0.0, 1.0);
+    let newer = buffer[(position + length - whole) % length];
+    let older = buffer[(position + length - whole - 1) % length];
+    newer * (1.0 - fraction) + older * fraction
+}
+
+/// First order allpass, shifting the phase around the frequency set by its coefficient.
+#[derive(Default)]
+struct AllpassStage {
+    input: f32,
+    output: f32,
+}
+
+impl AllpassStage {
+    fn process(&mut self, input: f32, coefficient: f32) -> f32 {
+        let output = coefficient * input + self.input - coefficient * self.output;
+        self.input = input;
+        self.output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(modulation: ModulationDto, frequency: f32) -> f32 {
+        let mut effect = Modulation::new(&modulation, 44100);
+        (0..44100)
+            .map(|sample| {
+                let value = (sample as f32 * frequency / 44100.0 * 2.0 * PI).sin();
+                effect.process(Frame::mono(value)).left.abs()
+            })
+            .skip(22050)
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_chorus_without_depth_is_a_fixed_delay() {
+        // given
+        let mut effect = Modulation::new(
+            &ModulationDto::Chorus {
+                rate: 1.0,
+                depth: 0.0,
+                mix: 1.0,
+            },
+            1000,
+        );
+
+        // when
+        let output = std::iter::once(Frame::mono(1.0))
+            .chain(std::iter::repeat(Frame::default()))
+            .take(30)
+            .map(|frame| effect.process(frame).left)
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(output.iter().position(|value| *value > 0.99), Some(20));
+    }
+
+    #[test]
+    fn test_phaser_keeps_level_when_fully_wet() {
+        // given
+        let phaser = ModulationDto::Phaser {
+            rate: 0.5,
+            depth: 1.0,
+            stages: 4,
+            feedback: 0.0,
+            mix: 1.0,
+        };
+
+        // when
+        let peak = response(phaser, 440.0);
+
+        // then
+        assert!((peak - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_phaser_mix_cancels_some_frequencies() {
+        // given
+        let phaser = |rate| ModulationDto::Phaser {
+            rate,
+            depth: 0.0,
+            stages: 2,
+            feedback: 0.0,
+            mix: 0.5,
+        };
+
+        // when
+        let notch = response(phaser(0.0), PHASER_LOWEST);
+
+        // then
+        assert!(notch < 0.05);
+    }
+}
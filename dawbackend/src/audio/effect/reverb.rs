@@ -0,0 +1,200 @@
+use dawlib::{AutomationParameter, ReverbDto};
+
+use super::{DelayLine, Effect, Mix};
+use crate::audio::Frame;
+
+// Freeverb tunings, given in samples at 44.1 kHz and scaled to the engine rate.
+const COMB_LENGTHS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_LENGTHS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const TUNING_SAMPLE_RATE: f32 = 44100.0;
+
+const INPUT_GAIN: f32 = 0.015;
+const WET_SCALE: f32 = 3.0;
+const ROOM_SCALE: f32 = 0.28;
+const ROOM_OFFSET: f32 = 0.7;
+const DAMPING_SCALE: f32 = 0.4;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+const SILENCE: f32 = 0.001;
+
+/// Freeverb: parallel damped combs followed by serial allpasses, one bank for each channel.
+pub struct Reverb {
+    left: Tank,
+    right: Tank,
+    pre_delay: DelayLine,
+    mix: Mix,
+    tail: usize,
+}
+
+impl Reverb {
+    pub fn new(reverb: &ReverbDto, sample_rate: usize) -> Self {
+        let scale = |length: usize| {
+            ((length as f32 * sample_rate as f32 / TUNING_SAMPLE_RATE) as usize).max(1)
+        };
+        let feedback = reverb.room_size.clamp(0.0, 1.0) * ROOM_SCALE + ROOM_OFFSET;
+        let damping = reverb.damping.clamp(0.0, 1.0) * DAMPING_SCALE;
+        let pre_delay = (reverb.pre_delay.max(0.0) * sample_rate as f32) as usize;
+
+        // The longest comb decides how long the reverb rings out.
+        let longest_comb = scale(COMB_LENGTHS[COMB_LENGTHS.len() - 1] + STEREO_SPREAD);
+        let passes = SILENCE.ln() / feedback.ln();
+        let allpasses = ALLPASS_LENGTHS
+            .iter()
+            .map(|length| scale(*length))
+            .sum::<usize>();
+
+        Self {
+            left: Tank::new(&scale, 0, feedback, damping),
+            right: Tank::new(&scale, STEREO_SPREAD, feedback, damping),
+            pre_delay: DelayLine::new(pre_delay),
+            mix: Mix::new(reverb.mix),
+            tail: pre_delay + (passes * longest_comb as f32) as usize + allpasses,
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, frame: Frame) -> Frame {
+        let delayed = self.pre_delay.delay(frame);
+        let input = (delayed.left + delayed.right) * INPUT_GAIN;
+        let wet = Frame::new(self.left.process(input), self.right.process(input));
+
+        self.mix.blend(frame, wet * WET_SCALE)
+    }
+
+    fn tail(&self) -> usize {
+        self.tail
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        if parameter == AutomationParameter::ReverbMix {
+            self.mix.set(value);
+        }
+    }
+
+    fn modulate(&mut self, parameter: AutomationParameter, offset: f32) {
+        if parameter == AutomationParameter::ReverbMix {
+            self.mix.modulate(offset);
+        }
+    }
+}
+
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Tank {
+    fn new(scale: &impl Fn(usize) -> usize, spread: usize, feedback: f32, damping: f32) -> Self {
+        Self {
+            combs: COMB_LENGTHS
+                .iter()
+                .map(|length| Comb {
+                    buffer: vec![0.0; scale(length + spread)],
+                    position: 0,
+                    feedback,
+                    damping,
+                    filter_state: 0.0,
+                })
+                .collect(),
+            allpasses: ALLPASS_LENGTHS
+                .iter()
+                .map(|length| Allpass {
+                    buffer: vec![0.0; scale(length + spread)],
+                    position: 0,
+                })
+                .collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let combined = self.combs.iter_mut().map(|comb| comb.process(input)).sum();
+        self.allpasses
+            .iter_mut()
+            .fold(combined, |signal, allpass| allpass.process(signal))
+    }
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    feedback: f32,
+    damping: f32,
+    filter_state: f32,
+}
+
+impl Comb {
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filter_state = output * (1.0 - self.damping) + self.filter_state * self.damping;
+        self.buffer[self.position] = input + self.filter_state * self.feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.position];
+        self.buffer[self.position] = input + buffered * ALLPASS_FEEDBACK;
+        self.position = (self.position + 1) % self.buffer.len();
+        buffered - input
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reverb(room_size: f32, pre_delay: f32) -> Reverb {
+        Reverb::new(
+            &ReverbDto {
+                room_size,
+                damping: 0.5,
+                pre_delay,
+                mix: 1.0,
+            },
+            44100,
+        )
+    }
+
+    #[test]
+    fn test_impulse_response_is_delayed_and_decays() {
+        // given
+        let mut reverb = reverb(0.5, 0.01);
+
+        // when
+        let response = std::iter::once(Frame::mono(1.0))
+            .chain(std::iter::repeat(Frame::default()))
+            .take(reverb.tail())
+            .map(|frame| reverb.process(frame).left.abs())
+            .collect::<Vec<_>>();
+
+        // then
+        assert!(response[..441].iter().all(|value| *value == 0.0));
+        let peak = response
+            .iter()
+            .fold(0.0, |peak: f32, value| peak.max(*value));
+        let end = &response[response.len() - 4410..];
+        assert!(peak > 0.01);
+        assert!(end.iter().all(|value| *value < peak * 0.001));
+    }
+
+    #[test]
+    fn test_larger_room_rings_longer() {
+        // given
+        let small = reverb(0.2, 0.0);
+        let large = reverb(0.9, 0.0);
+
+        // when
+        let (small, large) = (small.tail(), large.tail());
+
+        // then
+        assert!(large > small * 2);
+    }
+}
@@ -0,0 +1,204 @@
+use dawlib::{EnvelopeCurve, EnvelopeDto};
+
+use super::{matrix::Modulation, Frame, SoundNode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Finished,
+}
+
+pub struct Envelope {
+    attack: usize,
+    decay: usize,
+    sustain: f32,
+    release: usize,
+    curve: EnvelopeCurve,
+    gate: usize,
+    elapsed: usize,
+    stage: Stage,
+    position: usize,
+    release_level: f32,
+    value: f32,
+}
+
+impl Envelope {
+    pub fn new(envelope: &EnvelopeDto, sample_rate: usize, gate: usize) -> Self {
+        let to_samples = |seconds: f32| (seconds.max(0.0) * sample_rate as f32).round() as usize;
+
+        Self {
+            attack: to_samples(envelope.attack),
+            decay: to_samples(envelope.decay),
+            sustain: envelope.sustain.clamp(0.0, 1.0),
+            release: to_samples(envelope.release),
+            curve: envelope.curve,
+            gate,
+            elapsed: 0,
+            stage: Stage::Attack,
+            position: 0,
+            release_level: 0.0,
+            value: 0.0,
+        }
+    }
+
+    pub fn next_value(&mut self) -> f32 {
+        if self.elapsed == self.gate && self.stage != Stage::Finished {
+            self.release_level = self.value;
+            self.enter(Stage::Release);
+        }
+        self.elapsed += 1;
+
+        loop {
+            let (length, from, to, next_stage) = match self.stage {
+                Stage::Attack => (self.attack, 0.0, 1.0, Stage::Decay),
+                Stage::Decay => (self.decay, 1.0, self.sustain, Stage::Sustain),
+                Stage::Sustain => {
+                    self.value = self.sustain;
+                    return self.value;
+                }
+                Stage::Release => (self.release, self.release_level, 0.0, Stage::Finished),
+                Stage::Finished => {
+                    self.value = 0.0;
+                    return self.value;
+                }
+            };
+
+            if self.position >= length {
+                self.enter(next_stage);
+                continue;
+            }
+
+            let progress = self.position as f32 / length as f32;
+            self.value = from + (to - from) * shape(self.curve, progress);
+
+            self.position += 1;
+            if self.position >= length {
+                self.enter(next_stage);
+            }
+            return self.value;
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.stage == Stage::Finished
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.position = 0;
+    }
+}
+
+fn shape(curve: EnvelopeCurve, progress: f32) -> f32 {
+    match curve {
+        EnvelopeCurve::Linear => progress,
+        // Approaches the target like an RC circuit: fast at first, then settling.
+        EnvelopeCurve::Exponential => {
+            const STEEPNESS: f32 = 5.0;
+            (1.0 - f32::exp(-STEEPNESS * progress)) / (1.0 - f32::exp(-STEEPNESS))
+        }
+    }
+}
+
+pub struct EnvelopeNode<T: SoundNode> {
+    node: T,
+    envelope: Envelope,
+}
+
+impl<T: SoundNode> EnvelopeNode<T> {
+    pub fn new(node: T, envelope: Envelope) -> Self {
+        Self { node, envelope }
+    }
+}
+
+impl<T: SoundNode> SoundNode for EnvelopeNode<T> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        if self.envelope.finished() {
+            return None;
+        }
+
+        let level = self.envelope.next_value();
+        self.node.next_frame().map(|frame| frame * level)
+    }
+
+    fn ended(&self) -> bool {
+        self.envelope.finished() || self.node.ended()
+    }
+
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.node.modulate(modulation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Constant;
+
+    impl SoundNode for Constant {
+        fn next_frame(&mut self) -> Option<Frame> {
+            Some(Frame::mono(1.0))
+        }
+
+        fn ended(&self) -> bool {
+            false
+        }
+    }
+
+    fn envelope(attack: f32, decay: f32, sustain: f32, release: f32) -> EnvelopeDto {
+        EnvelopeDto {
+            attack,
+            decay,
+            sustain,
+            release,
+            curve: EnvelopeCurve::Linear,
+        }
+    }
+
+    #[test]
+    fn test_envelope_stages() {
+        // given
+        let mut envelope = Envelope::new(&envelope(0.004, 0.004, 0.5, 0.004), 1000, 12);
+
+        // when
+        let values = (0..20).map(|_| envelope.next_value()).collect::<Vec<f32>>();
+
+        // then
+        assert_eq!(&values[..4], &[0.0, 0.25, 0.5, 0.75]);
+        assert_eq!(&values[4..8], &[1.0, 0.875, 0.75, 0.625]);
+        assert_eq!(&values[8..12], &[0.5, 0.5, 0.5, 0.5]);
+        assert_eq!(&values[12..16], &[0.5, 0.375, 0.25, 0.125]);
+        assert_eq!(&values[16..], &[0.0, 0.0, 0.0, 0.0]);
+        assert!(envelope.finished());
+    }
+
+    #[test]
+    fn test_envelope_node_keeps_playing_during_release() {
+        // given
+        let mut node = EnvelopeNode::new(Constant, Envelope::new(&envelope(0.0, 0.0, 1.0, 0.1), 1000, 10));
+
+        // when
+        let played = std::iter::from_fn(|| node.next_frame()).count();
+
+        // then
+        assert_eq!(played, 110);
+        assert!(node.ended());
+    }
+
+    #[test]
+    fn test_envelope_released_before_attack_finished() {
+        // given
+        let mut envelope = Envelope::new(&envelope(0.01, 0.0, 1.0, 0.002), 1000, 5);
+
+        // when
+        let values = (0..8).map(|_| envelope.next_value()).collect::<Vec<f32>>();
+
+        // then
+        assert_eq!(&values[..5], &[0.0, 0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(&values[5..], &[0.4, 0.2, 0.0]);
+    }
+}
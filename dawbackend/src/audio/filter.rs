@@ -0,0 +1,269 @@
+use std::f32::consts::TAU;
+
+use dawlib::{AutomationParameter, FilterDto, FilterMode};
+
+use super::{envelope::Envelope, matrix::Modulation, Frame, SoundNode};
+
+const MIN_CUTOFF: f32 = 20.0;
+const MIN_RESONANCE: f32 = 0.1;
+
+/// RBJ cookbook biquad in transposed direct form II, with separate state for each channel.
+pub struct Biquad {
+    coefficients: Coefficients,
+    left: [f32; 2],
+    right: [f32; 2],
+}
+
+#[derive(Default, Clone, Copy)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    fn process(&self, input: f32, state: &mut [f32; 2]) -> f32 {
+        let output = self.b0 * input + state[0];
+        state[0] = self.b1 * input - self.a1 * output + state[1];
+        state[1] = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+impl Biquad {
+    pub fn new(
+        mode: FilterMode,
+        cutoff: f32,
+        resonance: f32,
+        gain: f32,
+        sample_rate: usize,
+    ) -> Self {
+        let mut biquad = Self {
+            coefficients: Coefficients::default(),
+            left: [0.0; 2],
+            right: [0.0; 2],
+        };
+        biquad.set(mode, cutoff, resonance, gain, sample_rate);
+        biquad
+    }
+
+    pub fn set(
+        &mut self,
+        mode: FilterMode,
+        cutoff: f32,
+        resonance: f32,
+        gain: f32,
+        sample_rate: usize,
+    ) {
+        let nyquist_margin = sample_rate as f32 * 0.45;
+        let omega = TAU * cutoff.clamp(MIN_CUTOFF, nyquist_margin) / sample_rate as f32;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * resonance.max(MIN_RESONANCE));
+        let a = 10f32.powf(gain / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match mode {
+            FilterMode::Lowpass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterMode::Highpass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterMode::Bandpass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterMode::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterMode::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterMode::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+
+        self.coefficients = Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        };
+    }
+
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        Frame::new(
+            self.coefficients.process(frame.left, &mut self.left),
+            self.coefficients.process(frame.right, &mut self.right),
+        )
+    }
+}
+
+pub struct FilterNode<T: SoundNode> {
+    node: T,
+    biquad: Biquad,
+    filter: FilterDto,
+    envelope: Envelope,
+    /// Cutoff offset in octaves from the modulation matrix.
+    modulation: f32,
+    sample_rate: usize,
+}
+
+impl<T: SoundNode> FilterNode<T> {
+    pub fn new(node: T, filter: FilterDto, envelope: Envelope, sample_rate: usize) -> Self {
+        Self {
+            node,
+            biquad: Biquad::new(
+                filter.mode,
+                filter.cutoff,
+                filter.resonance,
+                filter.gain,
+                sample_rate,
+            ),
+            filter,
+            envelope,
+            modulation: 0.0,
+            sample_rate,
+        }
+    }
+}
+
+impl<T: SoundNode> SoundNode for FilterNode<T> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        let level = self.envelope.next_value();
+        if self.filter.envelope_amount != 0.0 || self.modulation != 0.0 {
+            let octaves = self.filter.envelope_amount * level + self.modulation;
+            let cutoff = self.filter.cutoff * octaves.exp2();
+            self.biquad.set(
+                self.filter.mode,
+                cutoff,
+                self.filter.resonance,
+                self.filter.gain,
+                self.sample_rate,
+            );
+        }
+
+        self.node
+            .next_frame()
+            .map(|frame| self.biquad.process(frame))
+    }
+
+    fn ended(&self) -> bool {
+        self.node.ended()
+    }
+
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.modulation = modulation.cutoff;
+        self.node.modulate(modulation)
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        if parameter == AutomationParameter::Cutoff && value != self.filter.cutoff {
+            self.filter.cutoff = value;
+            self.biquad.set(
+                self.filter.mode,
+                value,
+                self.filter.resonance,
+                self.filter.gain,
+                self.sample_rate,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dawlib::EnvelopeDto;
+
+    use super::*;
+    use crate::audio::oscillator::{Oscillator, Waveshape};
+
+    fn peak(node: &mut impl SoundNode) -> f32 {
+        (0..4410)
+            .map(|_| node.next_frame().unwrap().left.abs())
+            .skip(2205)
+            .fold(0.0, f32::max)
+    }
+
+    fn lowpass(cutoff: f32, envelope_amount: f32) -> FilterDto {
+        FilterDto {
+            mode: FilterMode::Lowpass,
+            cutoff,
+            resonance: std::f32::consts::FRAC_1_SQRT_2,
+            gain: 0.0,
+            envelope: None,
+            envelope_amount,
+        }
+    }
+
+    #[test]
+    fn test_lowpass_attenuates_above_cutoff() {
+        // given
+        let envelope = || Envelope::new(&EnvelopeDto::default(), 44100, 44100);
+        let mut low = FilterNode::new(
+            Oscillator::new(Waveshape::Sine, 100.0, 44100),
+            lowpass(1000.0, 0.0),
+            envelope(),
+            44100,
+        );
+        let mut high = FilterNode::new(
+            Oscillator::new(Waveshape::Sine, 10000.0, 44100),
+            lowpass(1000.0, 0.0),
+            envelope(),
+            44100,
+        );
+
+        // when
+        let low = peak(&mut low);
+        let high = peak(&mut high);
+
+        // then
+        assert!((low - 1.0).abs() < 0.01);
+        assert!(high < 0.02);
+    }
+
+    #[test]
+    fn test_envelope_opens_cutoff() {
+        // given
+        let envelope = || Envelope::new(&EnvelopeDto::default(), 44100, 44100);
+        let mut closed = FilterNode::new(
+            Oscillator::new(Waveshape::Sine, 4000.0, 44100),
+            lowpass(500.0, 0.0),
+            envelope(),
+            44100,
+        );
+        let mut opened = FilterNode::new(
+            Oscillator::new(Waveshape::Sine, 4000.0, 44100),
+            lowpass(500.0, 5.0),
+            envelope(),
+            44100,
+        );
+
+        // when
+        let closed = peak(&mut closed);
+        let opened = peak(&mut opened);
+
+        // then
+        assert!(closed < 0.05);
+        assert!(opened > 0.9);
+    }
+}
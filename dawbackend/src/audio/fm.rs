@@ -0,0 +1,187 @@
+use std::f32::consts::TAU;
+
+use dawlib::{EnvelopeDto, FmOperatorDto, FmParametersDto, InstrumentDto, MidiKey};
+
+use super::{
+    boxed,
+    envelope::Envelope,
+    instrument::{Instrument, OSCILLATOR_LEVEL},
+    matrix::Modulation,
+    AudioError, Frame, SoundNode,
+};
+
+const OPERATOR_COUNT: std::ops::RangeInclusive<usize> = 2..=4;
+
+pub fn factory(
+    instrument: &InstrumentDto,
+    sample_rate: usize,
+) -> Result<Box<dyn Instrument>, AudioError> {
+    let invalid = |reason: String| {
+        AudioError::InvalidParameters(instrument.instrument_type().to_string(), reason)
+    };
+
+    let parameters = instrument
+        .parameters::<FmParametersDto>()
+        .map_err(|error| invalid(error.to_string()))?;
+
+    if !OPERATOR_COUNT.contains(&parameters.operators.len()) {
+        return Err(invalid(format!(
+            "expected between {} and {} operators",
+            OPERATOR_COUNT.start(),
+            OPERATOR_COUNT.end()
+        )));
+    }
+
+    for (index, operator) in parameters.operators.iter().enumerate() {
+        if operator.ratio <= 0.0 {
+            return Err(invalid(format!("operator {index} needs a positive ratio")));
+        }
+        if operator.modulates.is_some_and(|target| target >= index) {
+            return Err(invalid(format!(
+                "operator {index} can only modulate operators listed before it"
+            )));
+        }
+    }
+
+    Ok(Box::new(FmInstrument {
+        operators: parameters.operators,
+        envelope: instrument.envelope,
+        sample_rate,
+    }))
+}
+
+struct FmInstrument {
+    operators: Vec<FmOperatorDto>,
+    envelope: EnvelopeDto,
+    sample_rate: usize,
+}
+
+impl Instrument for FmInstrument {
+    fn voice(&self, key: MidiKey, gate: usize) -> Box<dyn SoundNode> {
+        let operators = self
+            .operators
+            .iter()
+            .map(|operator| Operator {
+                increment: key.frequency() * operator.ratio / self.sample_rate as f32,
+                index: operator.index,
+                modulates: operator.modulates,
+                envelope: Envelope::new(
+                    operator.envelope.as_ref().unwrap_or(&self.envelope),
+                    self.sample_rate,
+                    gate,
+                ),
+                phase: 0.0,
+            })
+            .collect::<Vec<_>>();
+
+        boxed(FmVoice {
+            modulation: vec![0.0; operators.len()],
+            operators,
+            pitch_ratio: 1.0,
+        })
+    }
+}
+
+struct Operator {
+    increment: f32,
+    index: f32,
+    modulates: Option<usize>,
+    envelope: Envelope,
+    phase: f32,
+}
+
+struct FmVoice {
+    operators: Vec<Operator>,
+    modulation: Vec<f32>,
+    pitch_ratio: f32,
+}
+
+impl SoundNode for FmVoice {
+    fn next_frame(&mut self) -> Option<Frame> {
+        if self.ended() {
+            return None;
+        }
+
+        self.modulation.fill(0.0);
+        let mut output = 0.0;
+
+        // Modulators always come after their targets, so a reverse pass settles every input first.
+        for (index, operator) in self.operators.iter_mut().enumerate().rev() {
+            let value = (operator.phase * TAU + self.modulation[index]).sin()
+                * operator.index
+                * operator.envelope.next_value();
+            operator.phase = (operator.phase + operator.increment * self.pitch_ratio).fract();
+
+            match operator.modulates {
+                Some(target) => self.modulation[target] += value,
+                None => output += value,
+            }
+        }
+
+        Some(Frame::mono(output * OSCILLATOR_LEVEL))
+    }
+
+    fn ended(&self) -> bool {
+        self.operators
+            .iter()
+            .filter(|operator| operator.modulates.is_none())
+            .all(|operator| operator.envelope.finished())
+    }
+
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.pitch_ratio = modulation.pitch_ratio();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn instrument(parameters: serde_json::Value) -> InstrumentDto {
+        serde_json::from_value(serde_json::json!({
+            "name": "Bell", "instrument_type": "fm", "gain": 0.0, "notes": [],
+            "parameters": parameters
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fm_rejects_forward_modulation() {
+        // given
+        let instrument = instrument(serde_json::json!({ "operators": [
+            { "ratio": 1.0, "index": 1.0, "modulates": 1 },
+            { "ratio": 3.5, "index": 2.0 }
+        ]}));
+
+        // when
+        let result = factory(&instrument, 44100);
+
+        // then
+        assert!(matches!(result, Err(AudioError::InvalidParameters(..))));
+    }
+
+    #[test]
+    fn test_fm_voice_without_modulation_is_sine() {
+        // given
+        let instrument = instrument(serde_json::json!({ "operators": [
+            { "ratio": 1.0, "index": 1.0 },
+            { "ratio": 2.0, "index": 0.0, "modulates": 0 }
+        ]}));
+        let mut voice = factory(&instrument, 44100)
+            .unwrap()
+            .voice(MidiKey::A4, 44100);
+
+        // when
+        let frames = (0..1000)
+            .map(|_| voice.next_frame().unwrap().left)
+            .collect::<Vec<_>>();
+
+        // then
+        let increment = MidiKey::A4.frequency() / 44100.0;
+        let mut envelope = Envelope::new(&EnvelopeDto::default(), 44100, 44100);
+        for (sample, frame) in frames.iter().enumerate() {
+            let expected = (sample as f32 * increment * TAU).sin() * envelope.next_value();
+            assert!((frame - expected * OSCILLATOR_LEVEL).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use dawlib::{EnvelopeDto, InstrumentDto, MidiKey};
+
+use super::{
+    boxed, drums,
+    envelope::{Envelope, EnvelopeNode},
+    fm,
+    oscillator::{Oscillator, Waveshape},
+    AudioError, GainNode, SoundNode,
+};
+
+// Raw oscillators run at full scale, unlike the kick, so they are brought down to leave headroom.
+pub(super) const OSCILLATOR_LEVEL: f32 = 0.2;
+
+pub trait Instrument: Send {
+    /// Creates a voice for the key, held for `gate` samples before its release starts.
+    fn voice(&self, key: MidiKey, gate: usize) -> Box<dyn SoundNode>;
+
+    /// Voices in the same choke group cut each other off, like open and closed hi-hats.
+    fn choke_group(&self, _key: MidiKey) -> Option<usize> {
+        None
+    }
+}
+
+pub type InstrumentFactory =
+    Box<dyn Fn(&InstrumentDto, usize) -> Result<Box<dyn Instrument>, AudioError> + Send + Sync>;
+
+pub struct InstrumentRegistry {
+    factories: HashMap<String, InstrumentFactory>,
+}
+
+impl InstrumentRegistry {
+    pub fn empty() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    pub fn register<F>(&mut self, instrument_type: &str, factory: F)
+    where
+        F: Fn(&InstrumentDto, usize) -> Result<Box<dyn Instrument>, AudioError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories
+            .insert(instrument_type.to_string(), Box::new(factory));
+    }
+
+    pub fn create(
+        &self,
+        instrument: &InstrumentDto,
+        sample_rate: usize,
+    ) -> Result<Box<dyn Instrument>, AudioError> {
+        let instrument_type = instrument.instrument_type();
+        let factory = self
+            .factories
+            .get(instrument_type)
+            .ok_or_else(|| AudioError::UnknownInstrument(instrument_type.to_string()))?;
+        factory(instrument, sample_rate)
+    }
+}
+
+impl Default for InstrumentRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("sawtooth", |instrument, sample_rate| {
+            Ok(Box::new(WaveformInstrument {
+                shape: Waveshape::Sawtooth,
+                envelope: instrument.envelope,
+                sample_rate,
+            }))
+        });
+        registry.register("sine", |instrument, sample_rate| {
+            Ok(Box::new(WaveformInstrument {
+                shape: Waveshape::Sine,
+                envelope: instrument.envelope,
+                sample_rate,
+            }))
+        });
+        registry.register("square", |instrument, sample_rate| {
+            Ok(Box::new(WaveformInstrument {
+                shape: Waveshape::Square,
+                envelope: instrument.envelope,
+                sample_rate,
+            }))
+        });
+        registry.register("kick", drums::kick_factory);
+        registry.register("drums", drums::kit_factory);
+        registry.register("fm", fm::factory);
+        registry
+    }
+}
+
+struct WaveformInstrument {
+    shape: Waveshape,
+    envelope: EnvelopeDto,
+    sample_rate: usize,
+}
+
+impl Instrument for WaveformInstrument {
+    fn voice(&self, key: MidiKey, gate: usize) -> Box<dyn SoundNode> {
+        boxed(GainNode::new(
+            EnvelopeNode::new(
+                Oscillator::new(self.shape, key.frequency(), self.sample_rate),
+                Envelope::new(&self.envelope, self.sample_rate, gate),
+            ),
+            OSCILLATOR_LEVEL,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn instrument(payload: serde_json::Value) -> InstrumentDto {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn test_registry_unknown_instrument_type() {
+        // given
+        let registry = InstrumentRegistry::default();
+        let instrument = instrument(serde_json::json!({
+            "name": "Lead", "instrument_type": "theremin", "gain": 0.0, "notes": []
+        }));
+
+        // when
+        let result = registry.create(&instrument, 44100);
+
+        // then
+        assert_eq!(
+            result.err(),
+            Some(AudioError::UnknownInstrument("theremin".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_registry_falls_back_to_name() {
+        // given
+        let registry = InstrumentRegistry::default();
+        let instrument = instrument(serde_json::json!({
+            "name": "sine", "gain": 0.0, "notes": []
+        }));
+
+        // when
+        let mut voice = registry
+            .create(&instrument, 44100)
+            .unwrap()
+            .voice(MidiKey::A4, 100);
+
+        // then
+        assert!(voice.next_frame().is_some());
+    }
+}
@@ -0,0 +1,40 @@
+use std::f32::consts::TAU;
+
+use dawlib::LfoShape;
+
+/// Low frequency oscillator, its values range from -1 to 1.
+pub struct Lfo {
+    shape: LfoShape,
+    phase: f32,
+    increment: f32,
+}
+
+impl Lfo {
+    /// `phase` is the starting point as a fraction of a cycle.
+    pub fn new(shape: LfoShape, rate: f32, sample_rate: usize, phase: f32) -> Self {
+        Self {
+            shape,
+            phase: phase.rem_euclid(1.0),
+            increment: rate.max(0.0) / sample_rate as f32,
+        }
+    }
+
+    pub fn next_value(&mut self) -> f32 {
+        let phase = self.phase;
+        let value = match self.shape {
+            LfoShape::Sine => (phase * TAU).sin(),
+            // Starts at zero rising, like the sine.
+            LfoShape::Triangle => 1.0 - 4.0 * ((phase + 0.25).fract() - 0.5).abs(),
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::Sawtooth => 2.0 * (phase + 0.5).fract() - 1.0,
+        };
+        self.phase = (self.phase + self.increment).fract();
+        value
+    }
+}
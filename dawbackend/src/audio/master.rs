@@ -0,0 +1,326 @@
+use std::{collections::VecDeque, f32::consts::PI};
+
+use dawlib::{CompressorDto, MasterDto};
+
+use super::{
+    effect::{DelayLine, Effect},
+    Frame,
+};
+
+const LOOKAHEAD_SECONDS: f32 = 0.005;
+const LIMITER_RELEASE_SECONDS: f32 = 0.05;
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Peaks above 0 dBFS, measured on the master output since the last report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clipping {
+    pub true_peak: f32,
+    pub overs: usize,
+}
+
+/// Optional glue compressor followed by a look-ahead limiter, metered for true-peak overs.
+pub struct MasterBus {
+    compressor: Option<Compressor>,
+    limiter: Limiter,
+    meter: TruePeakMeter,
+}
+
+impl MasterBus {
+    pub fn new(master: &MasterDto, sample_rate: usize) -> Self {
+        Self {
+            compressor: master
+                .compressor
+                .as_ref()
+                .map(|compressor| Compressor::new(compressor, sample_rate)),
+            limiter: Limiter::new(master.ceiling, sample_rate),
+            meter: TruePeakMeter::new(),
+        }
+    }
+
+    pub fn take_clipping(&mut self) -> Option<Clipping> {
+        self.meter.take_clipping()
+    }
+}
+
+impl Effect for MasterBus {
+    fn process(&mut self, frame: Frame) -> Frame {
+        let frame = match &mut self.compressor {
+            Some(compressor) => compressor.process(frame),
+            None => frame,
+        };
+        let frame = self.limiter.process(frame);
+        self.meter.measure(frame);
+        frame
+    }
+
+    fn tail(&self) -> usize {
+        self.limiter.tail()
+    }
+}
+
+fn to_gain(decibels: f32) -> f32 {
+    10f32.powf(decibels / 20.0)
+}
+
+fn to_decibels(gain: f32) -> f32 {
+    20.0 * gain.max(f32::MIN_POSITIVE).log10()
+}
+
+fn smoothing(seconds: f32, sample_rate: usize) -> f32 {
+    (-1.0 / (seconds.max(1e-4) * sample_rate as f32)).exp()
+}
+
+struct Compressor {
+    threshold: f32,
+    slope: f32,
+    makeup: f32,
+    attack: f32,
+    release: f32,
+    reduction: f32,
+}
+
+impl Compressor {
+    fn new(compressor: &CompressorDto, sample_rate: usize) -> Self {
+        Self {
+            threshold: compressor.threshold,
+            slope: 1.0 - 1.0 / compressor.ratio.max(1.0),
+            makeup: compressor.makeup,
+            attack: smoothing(compressor.attack, sample_rate),
+            release: smoothing(compressor.release, sample_rate),
+            reduction: 0.0,
+        }
+    }
+
+    fn process(&mut self, frame: Frame) -> Frame {
+        let level = to_decibels(frame.left.abs().max(frame.right.abs()));
+        let target = (level - self.threshold).max(0.0) * self.slope;
+        let coefficient = if target > self.reduction {
+            self.attack
+        } else {
+            self.release
+        };
+        self.reduction = target + (self.reduction - target) * coefficient;
+
+        frame * to_gain(self.makeup - self.reduction)
+    }
+}
+
+/// Brickwall limiter. The gain needed by every sample is held over the look-ahead window and
+/// averaged over the same window, so the gain has ramped down fully by the time a peak leaves
+/// the delay line.
+struct Limiter {
+    ceiling: f32,
+    release: f32,
+    window: usize,
+    sample: usize,
+    minimum: VecDeque<(usize, f32)>,
+    held: f32,
+    average: VecDeque<f32>,
+    sum: f64,
+    delay: DelayLine,
+}
+
+impl Limiter {
+    fn new(ceiling: f32, sample_rate: usize) -> Self {
+        let window = ((LOOKAHEAD_SECONDS * sample_rate as f32) as usize).max(1);
+        Self {
+            ceiling: to_gain(ceiling.min(0.0)),
+            release: smoothing(LIMITER_RELEASE_SECONDS, sample_rate),
+            window,
+            sample: 0,
+            minimum: VecDeque::with_capacity(window),
+            held: 1.0,
+            average: VecDeque::from(vec![1.0; window]),
+            sum: window as f64,
+            delay: DelayLine::new(window - 1),
+        }
+    }
+
+    fn process(&mut self, frame: Frame) -> Frame {
+        let peak = frame.left.abs().max(frame.right.abs());
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        while self
+            .minimum
+            .back()
+            .is_some_and(|(_, gain)| *gain >= required)
+        {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((self.sample, required));
+        while self
+            .minimum
+            .front()
+            .is_some_and(|(sample, _)| sample + self.window <= self.sample)
+        {
+            self.minimum.pop_front();
+        }
+        self.sample += 1;
+
+        let window_minimum = self.minimum.front().map_or(1.0, |(_, gain)| *gain);
+        let released = 1.0 - (1.0 - self.held) * self.release;
+        self.held = window_minimum.min(released);
+
+        self.sum += self.held as f64 - self.average.pop_front().unwrap_or(1.0) as f64;
+        self.average.push_back(self.held);
+        let gain = (self.sum / self.window as f64) as f32;
+
+        self.delay.delay(frame) * gain.min(1.0)
+    }
+
+    fn tail(&self) -> usize {
+        self.window
+    }
+}
+
+/// Peak meter on a four times oversampled signal, catching peaks between samples.
+struct TruePeakMeter {
+    phases: Vec<[f32; TAPS_PER_PHASE]>,
+    left: VecDeque<f32>,
+    right: VecDeque<f32>,
+    peak: f32,
+    overs: usize,
+}
+
+impl TruePeakMeter {
+    fn new() -> Self {
+        let length = OVERSAMPLING * TAPS_PER_PHASE;
+        let centre = (length - 1) as f32 / 2.0;
+        let tap = |index: usize| {
+            let position = (index as f32 - centre) / OVERSAMPLING as f32;
+            let sinc = if position == 0.0 {
+                1.0
+            } else {
+                (PI * position).sin() / (PI * position)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * index as f32 / (length - 1) as f32).cos();
+            sinc * window
+        };
+
+        Self {
+            phases: (0..OVERSAMPLING)
+                .map(|phase| {
+                    let mut taps = [0.0; TAPS_PER_PHASE];
+                    for (index, value) in taps.iter_mut().enumerate() {
+                        *value = tap(index * OVERSAMPLING + phase);
+                    }
+                    taps
+                })
+                .collect(),
+            left: VecDeque::from(vec![0.0; TAPS_PER_PHASE]),
+            right: VecDeque::from(vec![0.0; TAPS_PER_PHASE]),
+            peak: 0.0,
+            overs: 0,
+        }
+    }
+
+    fn measure(&mut self, frame: Frame) {
+        let mut peak = 0.0f32;
+        for (history, value) in [(&mut self.left, frame.left), (&mut self.right, frame.right)] {
+            history.pop_back();
+            history.push_front(value);
+            for taps in &self.phases {
+                let interpolated = history
+                    .iter()
+                    .zip(taps.iter())
+                    .map(|(sample, tap)| sample * tap)
+                    .sum::<f32>();
+                peak = peak.max(interpolated.abs());
+            }
+        }
+
+        if peak > 1.0 {
+            self.overs += 1;
+            self.peak = self.peak.max(peak);
+        }
+    }
+
+    fn take_clipping(&mut self) -> Option<Clipping> {
+        if self.overs == 0 {
+            return None;
+        }
+
+        let clipping = Clipping {
+            true_peak: to_decibels(self.peak),
+            overs: self.overs,
+        };
+        self.peak = 0.0;
+        self.overs = 0;
+        Some(clipping)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(amplitude: f32, frequency: f32, length: usize) -> impl Iterator<Item = Frame> {
+        (0..length).map(move |sample| {
+            Frame::mono(amplitude * (sample as f32 * frequency / 44100.0 * 2.0 * PI).sin())
+        })
+    }
+
+    #[test]
+    fn test_limiter_keeps_peaks_below_ceiling() {
+        // given
+        let mut limiter = Limiter::new(-1.0, 44100);
+        let ceiling = to_gain(-1.0);
+
+        // when
+        let output = sine(4.0, 1000.0, 44100)
+            .map(|frame| limiter.process(frame).left.abs())
+            .collect::<Vec<_>>();
+
+        // then
+        assert!(output.iter().all(|value| *value <= ceiling + 1e-4));
+        assert!(output.iter().any(|value| *value > ceiling * 0.9));
+    }
+
+    #[test]
+    fn test_true_peak_catches_inter_sample_overs() {
+        // given
+        let mut meter = TruePeakMeter::new();
+
+        // when
+        // A quarter of the sample rate, sampled 45° off its peaks: every sample reads 0.707.
+        for sample in 0..1000 {
+            let phase = (sample as f32 + 0.5) * PI / 2.0;
+            meter.measure(Frame::mono(1.2 * phase.sin()));
+        }
+        let clipping = meter.take_clipping();
+
+        // then
+        let clipping = clipping.unwrap();
+        assert!(clipping.true_peak > 1.0 && clipping.true_peak < 1.8);
+        assert!(meter.take_clipping().is_none());
+    }
+
+    #[test]
+    fn test_compressor_reduces_loud_signal() {
+        // given
+        let mut compressor = Compressor::new(
+            &CompressorDto {
+                threshold: -20.0,
+                ratio: 4.0,
+                attack: 0.001,
+                release: 0.1,
+                makeup: 0.0,
+            },
+            44100,
+        );
+
+        // when
+        let output = sine(1.0, 100.0, 44100)
+            .map(|frame| compressor.process(frame).left.abs())
+            .skip(22050)
+            .fold(0.0, f32::max);
+
+        // then
+        assert!(to_decibels(output) < -10.0);
+    }
+}
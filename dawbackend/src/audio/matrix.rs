@@ -0,0 +1,315 @@
+use dawlib::{
+    AutomationParameter, ModulationDestination, ModulationRoutingDto, ModulationSourceDto,
+    MAX_VELOCITY,
+};
+
+use super::{effect::Effect, envelope::Envelope, lfo::Lfo, Frame, SoundNode};
+
+/// Parameter offsets for the current sample, summed over all routings of a voice.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modulation {
+    /// In semitones.
+    pub pitch: f32,
+    /// In octaves.
+    pub cutoff: f32,
+    /// Factor applied to the voice output.
+    pub gain: f32,
+    pub pan: f32,
+}
+
+impl Default for Modulation {
+    fn default() -> Self {
+        Self {
+            pitch: 0.0,
+            cutoff: 0.0,
+            gain: 1.0,
+            pan: 0.0,
+        }
+    }
+}
+
+impl Modulation {
+    pub fn pitch_ratio(&self) -> f32 {
+        (self.pitch / 12.0).exp2()
+    }
+}
+
+enum Source {
+    Lfo(Lfo),
+    Envelope(Envelope),
+    Velocity(f32),
+}
+
+impl Source {
+    fn next_value(&mut self) -> f32 {
+        match self {
+            Source::Lfo(lfo) => lfo.next_value(),
+            Source::Envelope(envelope) => envelope.next_value(),
+            Source::Velocity(velocity) => *velocity,
+        }
+    }
+
+    fn is_bipolar(&self) -> bool {
+        matches!(self, Source::Lfo(_))
+    }
+}
+
+/// The modulation sources of a single note and where they are routed to.
+pub struct ModulationMatrix {
+    routings: Vec<(Source, ModulationDestination, f32)>,
+    // One entry per effect parameter routed to, reused on every sample.
+    effect_offsets: Vec<(AutomationParameter, f32)>,
+}
+
+impl ModulationMatrix {
+    pub fn new(
+        routings: &[ModulationRoutingDto],
+        velocity: u8,
+        gate: usize,
+        sample_rate: usize,
+    ) -> Self {
+        let mut effect_offsets = Vec::<(AutomationParameter, f32)>::new();
+        for parameter in routings
+            .iter()
+            .filter_map(|routing| routing.destination.effect_parameter())
+        {
+            if effect_offsets.iter().all(|(other, _)| *other != parameter) {
+                effect_offsets.push((parameter, 0.0));
+            }
+        }
+
+        Self {
+            routings: routings
+                .iter()
+                .map(|routing| {
+                    let source = match routing.source {
+                        ModulationSourceDto::Lfo { shape, rate, phase } => {
+                            Source::Lfo(Lfo::new(shape, rate, sample_rate, phase))
+                        }
+                        ModulationSourceDto::Envelope { envelope } => {
+                            Source::Envelope(Envelope::new(&envelope, sample_rate, gate))
+                        }
+                        ModulationSourceDto::Velocity => Source::Velocity(
+                            velocity.min(MAX_VELOCITY) as f32 / MAX_VELOCITY as f32,
+                        ),
+                    };
+                    (source, routing.destination, routing.depth)
+                })
+                .collect(),
+            effect_offsets,
+        }
+    }
+
+    pub fn next_value(&mut self) -> Modulation {
+        let mut modulation = Modulation::default();
+        for (source, destination, depth) in &mut self.routings {
+            let value = source.next_value();
+            match destination {
+                ModulationDestination::Pitch => modulation.pitch += value * *depth,
+                ModulationDestination::Cutoff => modulation.cutoff += value * *depth,
+                ModulationDestination::Pan => modulation.pan += value * *depth,
+                ModulationDestination::Gain => {
+                    let unipolar = if source.is_bipolar() {
+                        (value + 1.0) * 0.5
+                    } else {
+                        value
+                    };
+                    modulation.gain *= (1.0 - *depth * (1.0 - unipolar)).max(0.0);
+                }
+                // Shared by the voices, see `modulate_effects`.
+                _ => {}
+            }
+        }
+        modulation
+    }
+
+    /// Offsets the effect parameters routed to, summing the routings of each parameter.
+    pub fn modulate_effects(&mut self, effects: &mut dyn Effect) {
+        for (_, offset) in &mut self.effect_offsets {
+            *offset = 0.0;
+        }
+        for (source, destination, depth) in &mut self.routings {
+            let Some(parameter) = destination.effect_parameter() else {
+                continue;
+            };
+            let value = source.next_value() * *depth;
+            if let Some((_, offset)) = self
+                .effect_offsets
+                .iter_mut()
+                .find(|(other, _)| *other == parameter)
+            {
+                *offset += value;
+            }
+        }
+        for (parameter, offset) in &self.effect_offsets {
+            effects.modulate(*parameter, *offset);
+        }
+    }
+}
+
+/// Runs the modulation matrix of a voice, handing the parameter offsets down the node chain.
+pub struct ModulationNode<T: SoundNode> {
+    node: T,
+    matrix: ModulationMatrix,
+}
+
+impl<T: SoundNode> ModulationNode<T> {
+    pub fn new(node: T, matrix: ModulationMatrix) -> Self {
+        Self { node, matrix }
+    }
+}
+
+impl<T: SoundNode> SoundNode for ModulationNode<T> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        let modulation = self.matrix.next_value();
+        self.node.modulate(&modulation);
+        self.node.next_frame().map(|frame| frame * modulation.gain)
+    }
+
+    fn ended(&self) -> bool {
+        self.node.ended()
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        self.node.automate(parameter, value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dawlib::LfoShape;
+
+    use super::*;
+
+    fn routing(
+        source: ModulationSourceDto,
+        destination: ModulationDestination,
+        depth: f32,
+    ) -> ModulationRoutingDto {
+        ModulationRoutingDto {
+            source,
+            destination,
+            depth,
+        }
+    }
+
+    #[test]
+    fn test_lfo_vibrato_swings_pitch_by_depth() {
+        // given
+        let lfo = ModulationSourceDto::Lfo {
+            shape: LfoShape::Triangle,
+            rate: 1.0,
+            phase: 0.0,
+        };
+        let mut matrix = ModulationMatrix::new(
+            &[routing(lfo, ModulationDestination::Pitch, 2.0)],
+            MAX_VELOCITY,
+            100,
+            100,
+        );
+
+        // when
+        let pitches = (0..100)
+            .map(|_| matrix.next_value().pitch)
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(pitches[0], 0.0);
+        assert!((pitches[25] - 2.0).abs() < 1e-5);
+        assert!((pitches[75] + 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_velocity_and_tremolo_scale_gain() {
+        // given
+        let tremolo = ModulationSourceDto::Lfo {
+            shape: LfoShape::Square,
+            rate: 1.0,
+            phase: 0.5,
+        };
+        let mut matrix = ModulationMatrix::new(
+            &[
+                routing(
+                    ModulationSourceDto::Velocity,
+                    ModulationDestination::Gain,
+                    1.0,
+                ),
+                routing(tremolo, ModulationDestination::Gain, 0.5),
+            ],
+            MAX_VELOCITY / 2,
+            100,
+            100,
+        );
+
+        // when
+        let gain = matrix.next_value().gain;
+
+        // then
+        let velocity = (MAX_VELOCITY / 2) as f32 / MAX_VELOCITY as f32;
+        assert!((gain - velocity * 0.5).abs() < 1e-6);
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        offsets: Vec<(AutomationParameter, f32)>,
+    }
+
+    impl Effect for Recorder {
+        fn process(&mut self, frame: Frame) -> Frame {
+            frame
+        }
+
+        fn tail(&self) -> usize {
+            0
+        }
+
+        fn modulate(&mut self, parameter: AutomationParameter, offset: f32) {
+            self.offsets.push((parameter, offset));
+        }
+    }
+
+    #[test]
+    fn test_effect_routings_sum_per_parameter() {
+        // given
+        let mut matrix = ModulationMatrix::new(
+            &[
+                routing(
+                    ModulationSourceDto::Velocity,
+                    ModulationDestination::DelayMix,
+                    0.5,
+                ),
+                routing(
+                    ModulationSourceDto::Velocity,
+                    ModulationDestination::DelayMix,
+                    0.25,
+                ),
+                routing(
+                    ModulationSourceDto::Velocity,
+                    ModulationDestination::DelayTime,
+                    1.0,
+                ),
+                routing(
+                    ModulationSourceDto::Velocity,
+                    ModulationDestination::Pitch,
+                    1.0,
+                ),
+            ],
+            MAX_VELOCITY,
+            100,
+            100,
+        );
+        let mut recorder = Recorder::default();
+
+        // when
+        matrix.modulate_effects(&mut recorder);
+
+        // then
+        assert_eq!(
+            recorder.offsets,
+            vec![
+                (AutomationParameter::DelayMix, 0.75),
+                (AutomationParameter::DelayTime, 1.0)
+            ]
+        );
+    }
+}
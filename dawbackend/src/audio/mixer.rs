@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use dawlib::{BusDto, SendDto};
+
+use super::{
+    effect::{self, Effect},
+    pan_gains,
+    timeline::Timeline,
+    AudioError, Frame,
+};
+
+/// Level and balance applied to a channel or bus output, after its effects.
+pub struct Fader {
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+impl Fader {
+    /// `gain` is given in the units of the payload, see [`Fader::set_gain`].
+    pub fn new(gain: f32, pan: f32) -> Self {
+        let mut fader = Self {
+            gain: 1.0,
+            left: 1.0,
+            right: 1.0,
+        };
+        fader.set_gain(gain);
+        fader.set_pan(pan);
+        fader
+    }
+
+    /// Zero keeps the level, -30 silences.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = (gain + 30.0) / 30.0;
+    }
+
+    pub fn set_pan(&mut self, pan: f32) {
+        (self.left, self.right) = pan_gains(pan);
+    }
+
+    pub fn apply(&self, frame: Frame) -> Frame {
+        Frame::new(frame.left * self.left, frame.right * self.right) * self.gain
+    }
+}
+
+/// A channel send resolved to the index of its bus.
+pub struct ChannelSend {
+    bus: usize,
+    level: f32,
+    pre_fader: bool,
+}
+
+impl ChannelSend {
+    /// The part of the channel output sent, given the channel output before and after its fader.
+    pub fn tap(&self, pre_fader: Frame, post_fader: Frame) -> Frame {
+        if self.pre_fader {
+            pre_fader * self.level
+        } else {
+            post_fader * self.level
+        }
+    }
+}
+
+struct Bus {
+    effects: Vec<Box<dyn Effect>>,
+    fader: Fader,
+    output: Option<usize>,
+    input: Frame,
+    idle_samples: usize,
+}
+
+impl Bus {
+    fn process(&mut self) -> Frame {
+        let input = std::mem::take(&mut self.input);
+        if input == Frame::default() {
+            self.idle_samples = self.idle_samples.saturating_add(1);
+            if self.ended() {
+                return Frame::default();
+            }
+        } else {
+            self.idle_samples = 0;
+        }
+
+        self.fader.apply(self.effects.process(input))
+    }
+
+    fn ended(&self) -> bool {
+        self.idle_samples > self.effects.tail()
+    }
+}
+
+/// Group and return buses, kept in an order where every bus comes before the bus it feeds.
+pub struct Mixer {
+    buses: Vec<Bus>,
+    indices: HashMap<String, usize>,
+}
+
+impl Mixer {
+    pub fn new(
+        buses: &[BusDto],
+        timeline: &Timeline,
+        sample_rate: usize,
+    ) -> Result<Self, AudioError> {
+        let mut positions = HashMap::new();
+        for (position, bus) in buses.iter().enumerate() {
+            if positions.insert(bus.name.as_str(), position).is_some() {
+                return Err(AudioError::InvalidRouting(format!(
+                    "bus `{}` is defined twice",
+                    bus.name
+                )));
+            }
+        }
+        let target = |output: &Option<String>| match output {
+            Some(name) => positions
+                .get(name.as_str())
+                .copied()
+                .map(Some)
+                .ok_or_else(|| AudioError::InvalidRouting(format!("unknown bus `{name}`"))),
+            None => Ok(None),
+        };
+
+        // Every bus is processed after the buses feeding it, so the longest paths to the
+        // master go first. A path longer than the number of buses has to be a loop.
+        let mut distances = Vec::with_capacity(buses.len());
+        for bus in buses {
+            let mut distance = 0;
+            let mut output = target(&bus.output)?;
+            while let Some(next) = output {
+                distance += 1;
+                if distance > buses.len() {
+                    return Err(AudioError::InvalidRouting(format!(
+                        "bus `{}` feeds back into itself",
+                        bus.name
+                    )));
+                }
+                output = target(&buses[next].output)?;
+            }
+            distances.push(distance);
+        }
+        let mut order = (0..buses.len()).collect::<Vec<_>>();
+        order.sort_by_key(|position| std::cmp::Reverse(distances[*position]));
+
+        let indices = order
+            .iter()
+            .enumerate()
+            .map(|(index, position)| (buses[*position].name.clone(), index))
+            .collect::<HashMap<_, _>>();
+        let buses = order
+            .iter()
+            .map(|position| {
+                let bus = &buses[*position];
+                Bus {
+                    effects: effect::chain(&bus.effects, timeline, sample_rate),
+                    fader: Fader::new(bus.gain, bus.pan),
+                    output: bus.output.as_ref().map(|name| indices[name]),
+                    input: Frame::default(),
+                    idle_samples: usize::MAX,
+                }
+            })
+            .collect();
+
+        Ok(Self { buses, indices })
+    }
+
+    /// Index of the bus an instrument plays into, `None` for the master.
+    pub fn output(&self, output: &Option<String>) -> Result<Option<usize>, AudioError> {
+        output.as_ref().map(|name| self.index(name)).transpose()
+    }
+
+    pub fn send(&self, send: &SendDto) -> Result<ChannelSend, AudioError> {
+        Ok(ChannelSend {
+            bus: self.index(&send.bus)?,
+            level: send.level.max(0.0),
+            pre_fader: send.pre_fader,
+        })
+    }
+
+    fn index(&self, name: &str) -> Result<usize, AudioError> {
+        self.indices
+            .get(name)
+            .copied()
+            .ok_or_else(|| AudioError::InvalidRouting(format!("unknown bus `{name}`")))
+    }
+
+    pub fn feed(&mut self, bus: usize, frame: Frame) {
+        self.buses[bus].input += frame;
+    }
+
+    pub fn feed_send(&mut self, send: &ChannelSend, pre_fader: Frame, post_fader: Frame) {
+        self.feed(send.bus, send.tap(pre_fader, post_fader));
+    }
+
+    /// Runs every bus on what it was fed since the last call, returning what reaches the master.
+    pub fn process(&mut self) -> Frame {
+        let mut master = Frame::default();
+        for index in 0..self.buses.len() {
+            let output = self.buses[index].process();
+            match self.buses[index].output {
+                Some(target) => self.buses[target].input += output,
+                None => master += output,
+            }
+        }
+        master
+    }
+
+    pub fn ended(&self) -> bool {
+        self.buses.iter().all(|bus| bus.ended())
+    }
+
+    pub fn follow_tempo(&mut self, samples_per_beat: f64) {
+        for bus in &mut self.buses {
+            bus.effects.follow_tempo(samples_per_beat);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bus(name: &str, output: Option<&str>) -> BusDto {
+        BusDto {
+            name: name.to_string(),
+            gain: 0.0,
+            pan: 0.0,
+            effects: Vec::new(),
+            output: output.map(str::to_string),
+        }
+    }
+
+    fn mixer(buses: &[BusDto]) -> Result<Mixer, AudioError> {
+        Mixer::new(buses, &Timeline::new(44100, 120.0, &[], 96), 44100)
+    }
+
+    #[test]
+    fn test_groups_feed_through_to_master() {
+        // given
+        let mut mixer = mixer(&[
+            bus("drums", Some("group")),
+            bus("group", None),
+            bus("reverb", Some("group")),
+        ])
+        .unwrap();
+        let reverb = mixer.output(&Some("reverb".to_string())).unwrap().unwrap();
+        let drums = mixer.output(&Some("drums".to_string())).unwrap().unwrap();
+
+        // when
+        mixer.feed(reverb, Frame::mono(0.25));
+        mixer.feed(drums, Frame::mono(0.5));
+        let output = mixer.process();
+
+        // then
+        assert!((output.left - 0.75).abs() < 1e-6);
+        assert!((output.right - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_routing_loops_are_rejected() {
+        // given
+        let buses = [bus("a", Some("b")), bus("b", Some("a"))];
+
+        // when
+        let result = mixer(&buses);
+
+        // then
+        assert!(matches!(result, Err(AudioError::InvalidRouting(_))));
+    }
+
+    #[test]
+    fn test_pre_fader_send_ignores_channel_fader() {
+        // given
+        let mixer = mixer(&[bus("reverb", None)]).unwrap();
+        let send = |pre_fader| SendDto {
+            bus: "reverb".to_string(),
+            level: 0.5,
+            pre_fader,
+        };
+        let (pre, post) = (
+            mixer.send(&send(true)).unwrap(),
+            mixer.send(&send(false)).unwrap(),
+        );
+        let fader = Fader::new(-30.0, 0.0);
+        let frame = Frame::mono(1.0);
+
+        // when
+        let (pre, post) = (
+            pre.tap(frame, fader.apply(frame)),
+            post.tap(frame, fader.apply(frame)),
+        );
+
+        // then
+        assert!((pre.left - 0.5).abs() < 1e-6);
+        assert_eq!(post, Frame::default());
+    }
+}
@@ -1,84 +1,261 @@
-use std::time::Duration;
-
-use dawlib::InstrumentDto;
-use wavegen::{sawtooth, sine, square, wf, Precision, SampleType, Waveform};
-
+use std::{
+    f32::consts::{FRAC_PI_4, SQRT_2},
+    fmt::Display,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Range},
+};
+
+use dawlib::{
+    AutomationParameter, FilterDto, InstrumentPayloadDto, Meter, MidiKey, ModulationRoutingDto,
+};
+
+use self::{
+    automation::AutomationLane,
+    effect::{Effect, EffectNode},
+    envelope::Envelope,
+    filter::FilterNode,
+    instrument::{Instrument, InstrumentRegistry},
+    master::{Clipping, MasterBus},
+    matrix::{Modulation, ModulationMatrix, ModulationNode},
+    mixer::{ChannelSend, Fader, Mixer},
+    timeline::Timeline,
+    voice::VoiceAllocator,
+};
+
+pub mod automation;
+pub mod drums;
+pub mod effect;
+pub mod envelope;
+pub mod filter;
+pub mod fm;
+pub mod instrument;
+pub mod lfo;
+pub mod master;
+pub mod matrix;
+pub mod mixer;
+pub mod oscillator;
+pub mod sampler;
 pub mod streaming;
+pub mod timeline;
+pub mod voice;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioError {
+    UnknownInstrument(String),
+    InvalidParameters(String, String),
+    SampleUnavailable(String, String),
+    InvalidRouting(String),
+}
 
-const DEFAULT_SAMPLE_RATE: f32 = 44100.0;
+impl Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::UnknownInstrument(instrument_type) => {
+                write!(f, "Unknown instrument type `{instrument_type}`.")
+            }
+            AudioError::InvalidParameters(instrument_type, reason) => {
+                write!(f, "Invalid parameters for `{instrument_type}`: {reason}.")
+            }
+            AudioError::SampleUnavailable(path, reason) => {
+                write!(f, "Sample `{path}` could not be loaded: {reason}.")
+            }
+            AudioError::InvalidRouting(reason) => write!(f, "Invalid mixer routing: {reason}."),
+        }
+    }
+}
 
 pub struct MusicBox {
-    instruments: Vec<InstrumentDto>,
-    playing_instruments: Vec<Box<dyn SoundNode>>,
-    samples_per_beat: usize,
+    channels: Vec<Channel>,
+    mixer: Mixer,
+    timeline: Timeline,
+    samples_per_beat: f64,
+    notes: Vec<ScheduledNote>,
+    next_note: usize,
     current_sample: usize,
+    loop_region: Option<Range<usize>>,
+    // Samples rendered so far, unlike the current sample it keeps counting through loops.
+    elapsed: usize,
+    sample_rate: usize,
+    master: MasterBus,
+    idle_samples: usize,
+}
+
+struct Channel {
+    instrument: Box<dyn Instrument>,
+    filter: Option<FilterDto>,
+    modulation_routings: Vec<ModulationRoutingDto>,
+    effect_routings: Vec<ModulationRoutingDto>,
+    // Sources of the latest note routed to the effect parameters.
+    effect_modulation: Option<ModulationMatrix>,
+    bus: EffectNode<VoiceAllocator, Vec<Box<dyn Effect>>>,
+    automation: Vec<AutomationLane>,
+    fader: Fader,
+    output: Option<usize>,
+    sends: Vec<ChannelSend>,
+}
+
+impl Channel {
+    fn automate(&mut self, sample: usize) {
+        for lane in &mut self.automation {
+            let value = lane.value_at(sample);
+            match lane.parameter() {
+                AutomationParameter::Gain => self.fader.set_gain(value),
+                AutomationParameter::Pan => self.fader.set_pan(value),
+                AutomationParameter::Cutoff => {
+                    // Later notes start from the automated cutoff as well.
+                    if let Some(filter) = &mut self.filter {
+                        filter.cutoff = value;
+                    }
+                    self.bus
+                        .node_mut()
+                        .automate(AutomationParameter::Cutoff, value);
+                }
+                parameter => self.bus.effect_mut().automate(parameter, value),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ScheduledNote {
+    channel: usize,
+    key: MidiKey,
+    start: usize,
+    length: usize,
+    velocity: u8,
 }
 
 impl Iterator for MusicBox {
-    type Item = f32;
+    type Item = Frame;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.instruments.is_empty() && self.playing_instruments.is_empty() {
-            return None;
+        if let Some(region) = &self.loop_region {
+            if self.current_sample >= region.end {
+                // Notes still sounding at the end of the region would ring on into its start.
+                for channel in &mut self.channels {
+                    channel.bus.node_mut().fade_out_all();
+                }
+                self.jump_to(region.start);
+            }
+        }
+
+        if self.loop_region.is_none()
+            && self.next_note == self.notes.len()
+            && self.channels.iter().all(|channel| channel.bus.ended())
+            && self.mixer.ended()
+        {
+            // The master bus lags behind by its look-ahead, flush it before stopping.
+            if self.idle_samples >= self.master.tail() {
+                return None;
+            }
+            self.idle_samples += 1;
+        } else {
+            self.idle_samples = 0;
         }
 
         self.update_state();
+        self.follow_tempo();
+
+        let mut frame = Frame::default();
+        for channel in &mut self.channels {
+            channel.automate(self.current_sample);
+            if let Some(matrix) = &mut channel.effect_modulation {
+                matrix.modulate_effects(channel.bus.effect_mut());
+            }
+            let Some(pre_fader) = channel.bus.next_frame() else {
+                continue;
+            };
 
-        let sample = self
-            .playing_instruments
-            .iter_mut()
-            .filter_map(|instrument| instrument.next_sample())
-            .sum::<f32>();
+            let post_fader = channel.fader.apply(pre_fader);
+            for send in &channel.sends {
+                self.mixer.feed_send(send, pre_fader, post_fader);
+            }
+            match channel.output {
+                Some(bus) => self.mixer.feed(bus, post_fader),
+                None => frame += post_fader,
+            }
+        }
+        frame += self.mixer.process();
 
         self.current_sample += 1;
+        self.elapsed += 1;
 
-        Some(sample)
+        Some(self.master.process(frame))
     }
 }
 
-struct PlayedWaveform<T: SampleType, P: Precision> {
-    waveform: Waveform<T, P>,
-    current_sample: usize,
-    sample_count: usize,
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub left: f32,
+    pub right: f32,
 }
 
-impl<P: Precision> SoundNode for PlayedWaveform<f32, P> {
-    fn next_sample(&mut self) -> Option<f32> {
-        if self.current_sample >= self.sample_count {
-            return None;
-        }
+impl Frame {
+    pub fn new(left: f32, right: f32) -> Self {
+        Self { left, right }
+    }
+
+    pub fn mono(sample: f32) -> Self {
+        Self::new(sample, sample)
+    }
+}
 
-        let edge_smoother = 1.0f32
-            .min(self.current_sample as f32 / Self::EDGE_SMOOTH)
-            .min((self.sample_count - self.current_sample) as f32 / Self::EDGE_SMOOTH);
+impl Add for Frame {
+    type Output = Frame;
 
-        let result = self.waveform.iter().nth(self.current_sample).unwrap() * edge_smoother * 0.2;
-        self.current_sample += 1;
-        Some(result)
+    fn add(self, other: Frame) -> Frame {
+        Frame::new(self.left + other.left, self.right + other.right)
     }
+}
 
-    fn ended(&self) -> bool {
-        self.current_sample >= self.sample_count
+impl AddAssign for Frame {
+    fn add_assign(&mut self, other: Frame) {
+        *self = *self + other;
     }
 }
 
-struct Kick {
-    sample_rate: usize,
-    current_sample: usize,
+impl Mul<f32> for Frame {
+    type Output = Frame;
+
+    fn mul(self, value: f32) -> Frame {
+        Frame::new(self.left * value, self.right * value)
+    }
 }
 
-impl Kick {
-    fn new(sample_rate: usize) -> Self {
-        Self {
-            sample_rate,
-            current_sample: 0,
-        }
+impl Sum for Frame {
+    fn sum<I: Iterator<Item = Frame>>(iter: I) -> Frame {
+        iter.fold(Frame::default(), Add::add)
     }
 }
 
-trait SoundNode: Send {
-    fn next_sample(&mut self) -> Option<f32>;
+pub trait SoundNode: Send {
+    fn next_frame(&mut self) -> Option<Frame>;
     fn ended(&self) -> bool;
+
+    /// Applies the modulation for the next frame. Nodes wrapping another node pass it on,
+    /// nodes without a modulated parameter ignore it.
+    fn modulate(&mut self, _modulation: &Modulation) {}
+
+    /// Sets an automated parameter, passed on the same way as the modulation.
+    fn automate(&mut self, _parameter: AutomationParameter, _value: f32) {}
+}
+
+impl SoundNode for Box<dyn SoundNode> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        self.as_mut().next_frame()
+    }
+
+    fn ended(&self) -> bool {
+        self.as_ref().ended()
+    }
+
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.as_mut().modulate(modulation)
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        self.as_mut().automate(parameter, value)
+    }
 }
 
 struct CompoundSoundNode<T: SoundNode> {
@@ -86,8 +263,8 @@ struct CompoundSoundNode<T: SoundNode> {
 }
 
 impl<T: SoundNode> SoundNode for CompoundSoundNode<T> {
-    fn next_sample(&mut self) -> Option<f32> {
-        self.nodes.iter_mut().map(|node| node.next_sample()).sum()
+    fn next_frame(&mut self) -> Option<Frame> {
+        self.nodes.iter_mut().map(|node| node.next_frame()).sum()
     }
 
     fn ended(&self) -> bool {
@@ -107,141 +284,201 @@ impl<T: SoundNode> GainNode<T> {
 }
 
 impl<T: SoundNode> SoundNode for GainNode<T> {
-    fn next_sample(&mut self) -> Option<f32> {
-        self.node.next_sample().map(|sample| sample * self.value)
+    fn next_frame(&mut self) -> Option<Frame> {
+        self.node.next_frame().map(|frame| frame * self.value)
     }
 
     fn ended(&self) -> bool {
         self.node.ended()
     }
-}
 
-struct SimpleDelayNode<T: SoundNode> {
-    node: T,
-    current_sample: usize,
-    sample_delay: usize,
-    buffer: Vec<f32>,
-    remaining_samples: usize,
-}
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.node.modulate(modulation)
+    }
 
-struct DelayReverb<T: SoundNode> {
-    node: SimpleDelayNode<SimpleDelayNode<SimpleDelayNode<T>>>,
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        self.node.automate(parameter, value)
+    }
 }
 
-impl<T: SoundNode> DelayReverb<T> {
-    fn new(node: T, sample_rate: usize) -> Self {
-        Self {
-            node: SimpleDelayNode::new(
-                SimpleDelayNode::new(
-                    SimpleDelayNode::new(node, sample_rate, Duration::from_secs_f32(0.1)),
-                    sample_rate,
-                    Duration::from_secs_f32(0.2),
-                ),
-                sample_rate,
-                Duration::from_secs_f32(0.3)
-            ),
-        }
-    }
+struct PanNode<T: SoundNode> {
+    node: T,
+    pan: f32,
+    offset: f32,
+    left: f32,
+    right: f32,
 }
 
-impl<T: SoundNode> SoundNode for DelayReverb<T> {
-    fn next_sample(&mut self) -> Option<f32> {
-        self.node.next_sample()
+impl<T: SoundNode> PanNode<T> {
+    fn new(node: T, pan: f32) -> Self {
+        let mut node = Self {
+            node,
+            pan,
+            offset: 0.0,
+            left: 0.0,
+            right: 0.0,
+        };
+        node.set_gains();
+        node
     }
 
-    fn ended(&self) -> bool {
-        self.node.ended()
+    fn set_gains(&mut self) {
+        (self.left, self.right) = pan_gains(self.pan + self.offset);
     }
 }
 
-impl<T: SoundNode> SimpleDelayNode<T> {
-    fn new(node: T, sample_rate: usize, delay: Duration) -> Self {
-        let sample_delay = (sample_rate as f32 * delay.as_secs_f32()) as usize;
-        Self {
-            node,
-            current_sample: 0,
-            sample_delay,
-            buffer: vec![0.0; sample_delay],
-            remaining_samples: 0,
-        }
-    }
+/// Constant-power law, scaled so that a centred signal keeps its level.
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos() * SQRT_2, angle.sin() * SQRT_2)
 }
 
-impl<T: SoundNode> SoundNode for SimpleDelayNode<T> {
-    fn next_sample(&mut self) -> Option<f32> {
-        let result = self.node.next_sample();
-        let buffer_position = self.current_sample % self.sample_delay;
-
-        let result = if self.current_sample < self.sample_delay {
-            if let Some(sample) = &result {
-                self.buffer[buffer_position] = *sample;
-            }
-            self.remaining_samples += 1;
-            result
-        } else {
-            let delayed_sample = self.buffer[buffer_position] * 0.4;
-            if let Some(sample) = &result {
-                self.buffer[buffer_position] = *sample;
-                Some(sample + delayed_sample)
-            } else if self.remaining_samples > 0 {
-                self.remaining_samples -= 1;
-                Some(delayed_sample)
-            } else {
-                None
-            }
-        };
-
-        self.current_sample += 1;
-        result
+impl<T: SoundNode> SoundNode for PanNode<T> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        self.node
+            .next_frame()
+            .map(|frame| Frame::new(frame.left * self.left, frame.right * self.right))
     }
 
     fn ended(&self) -> bool {
-        self.node.ended() && self.remaining_samples == 0
+        self.node.ended()
     }
-}
 
-impl SoundNode for Kick {
-    fn next_sample(&mut self) -> Option<f32> {
-        if self.ended() {
-            return None;
+    fn modulate(&mut self, modulation: &Modulation) {
+        if modulation.pan != self.offset {
+            self.offset = modulation.pan;
+            self.set_gains();
         }
-
-        let time = self.current_sample as f32 / self.sample_rate as f32;
-
-        let result = f32::sin((2000.0 * f32::exp(-15.0 * time)) * time);
-        self.current_sample += 1;
-        Some(result)
+        self.node.modulate(modulation)
     }
 
-    fn ended(&self) -> bool {
-        self.current_sample >= self.sample_rate
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        self.node.automate(parameter, value)
     }
 }
 
-impl<T: SampleType + std::ops::Mul<f32, Output = T>, P: Precision> PlayedWaveform<T, P> {
-    const EDGE_SMOOTH: f32 = 400.0;
-    pub fn new(waveform: Waveform<T, P>, sample_count: usize) -> PlayedWaveform<T, P> {
-        PlayedWaveform {
-            waveform,
+impl MusicBox {
+    pub fn new(
+        payload: InstrumentPayloadDto,
+        sample_rate: usize,
+        registry: &InstrumentRegistry,
+    ) -> Result<Self, AudioError> {
+        let timeline = Timeline::new(
+            sample_rate,
+            payload.tempo,
+            &payload.tempo_changes,
+            payload.resolution,
+        );
+        let meter = Meter::new(&payload.time_signatures, payload.resolution);
+        let master = MasterBus::new(&payload.master, sample_rate);
+        let mixer = Mixer::new(&payload.buses, &timeline, sample_rate)?;
+
+        let channels = payload
+            .instruments
+            .iter()
+            .map(|instrument| {
+                let automation = instrument
+                    .automation
+                    .iter()
+                    .filter_map(|lane| AutomationLane::new(lane, &timeline))
+                    .collect::<Vec<_>>();
+                let (effect_routings, modulation_routings) = instrument
+                    .modulation_routings
+                    .iter()
+                    .partition(|routing| routing.destination.effect_parameter().is_some());
+
+                Ok(Channel {
+                    instrument: registry.create(instrument, sample_rate)?,
+                    filter: instrument.filter.map(|filter| FilterDto {
+                        envelope: filter.envelope.or(Some(instrument.envelope)),
+                        ..filter
+                    }),
+                    modulation_routings,
+                    effect_routings,
+                    effect_modulation: None,
+                    bus: EffectNode::new(
+                        VoiceAllocator::new(instrument.polyphony, sample_rate),
+                        effect::chain(&instrument.effects, &timeline, sample_rate),
+                    ),
+                    automation,
+                    fader: Fader::new(instrument.gain, instrument.pan),
+                    output: mixer.output(&instrument.output)?,
+                    sends: instrument
+                        .sends
+                        .iter()
+                        .map(|send| mixer.send(send))
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<Vec<_>, AudioError>>()?;
+
+        let mut notes = payload
+            .instruments
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, instrument)| {
+                let (timeline, meter) = (&timeline, &meter);
+                let swing = instrument.swing.unwrap_or(payload.swing);
+                instrument.notes.into_iter().map(move |note| {
+                    let tick = note.start_tick(meter);
+                    let start = timeline.swung_sample_at(tick, &swing);
+                    ScheduledNote {
+                        channel: index,
+                        key: note.key,
+                        start,
+                        length: timeline.swung_sample_at(tick + note.length, &swing) - start,
+                        velocity: note.velocity,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        notes.sort_by_key(|note| note.start);
+
+        Ok(Self {
+            channels,
+            mixer,
+            samples_per_beat: timeline.samples_per_beat_at(0),
+            timeline,
+            notes,
+            next_note: 0,
             current_sample: 0,
-            sample_count,
-        }
+            loop_region: None,
+            elapsed: 0,
+            sample_rate,
+            master,
+            idle_samples: 0,
+        })
     }
-}
 
-impl MusicBox {
-    pub fn new(tempo: usize, instruments: Vec<InstrumentDto>) -> Self {
-        let modifier = tempo as f32 / 60.0;
-        let samples_per_beat = (DEFAULT_SAMPLE_RATE / modifier) as usize;
-        Self {
-            instruments,
-            playing_instruments: vec![],
-            current_sample: 0,
-            samples_per_beat,
-        }
+    /// The sample where the last note is let go of and leaves the master bus. The song lasts at
+    /// least this long, the releases and effect tails not included.
+    pub fn notes_end(&self) -> usize {
+        let end = self
+            .notes
+            .iter()
+            .map(|note| note.start + note.length)
+            .max()
+            .unwrap_or(0);
+        end + self.master.tail()
     }
 
-    pub fn chunk(&mut self, size: usize) -> Result<Vec<f32>, Vec<f32>> {
+    /// Renders until every voice has died away and left the master bus, cutting the effect tails.
+    pub fn until_voices_end(mut self) -> impl Iterator<Item = Frame> {
+        let mut flush = self.master.tail();
+        std::iter::from_fn(move || {
+            let voices_ended = self.next_note == self.notes.len()
+                && self
+                    .channels
+                    .iter()
+                    .all(|channel| channel.bus.node().is_empty());
+            if voices_ended {
+                flush = flush.checked_sub(1)?;
+            }
+            self.next()
+        })
+    }
+
+    pub fn chunk(&mut self, size: usize) -> Result<Vec<Frame>, Vec<Frame>> {
         let mut output = Vec::with_capacity(size);
 
         for _ in 0..size {
@@ -255,81 +492,278 @@ impl MusicBox {
         Ok(output)
     }
 
-    fn update_state(&mut self) {
-        let current_beat = self.current_sample / self.samples_per_beat;
+    /// True-peak overs on the master output since the last call.
+    pub fn take_clipping(&mut self) -> Option<Clipping> {
+        self.master.take_clipping()
+    }
 
-        if self.current_sample % self.samples_per_beat != 0 {
+    fn follow_tempo(&mut self) {
+        let samples_per_beat = self.timeline.samples_per_beat_at(self.current_sample);
+        if samples_per_beat == self.samples_per_beat {
             return;
         }
 
-        self.playing_instruments.retain(|sound| !sound.ended());
+        self.samples_per_beat = samples_per_beat;
+        for channel in &mut self.channels {
+            channel.bus.effect_mut().follow_tempo(samples_per_beat);
+        }
+        self.mixer.follow_tempo(samples_per_beat);
+    }
 
-        let mut new_instruments = self
-            .instruments
+    /// Moves playback to `tick` without rendering what comes before it.
+    pub fn seek(&mut self, tick: usize) {
+        self.jump_to(self.timeline.sample_at(tick));
+    }
+
+    /// Repeats the ticks from `start` up to `end` once playback reaches `end`.
+    pub fn repeat(&mut self, start: usize, end: usize) {
+        let (start, end) = (self.timeline.sample_at(start), self.timeline.sample_at(end));
+        self.loop_region = (start < end).then_some(start..end);
+    }
+
+    fn jump_to(&mut self, sample: usize) {
+        self.current_sample = sample;
+        self.next_note = self.notes.partition_point(|note| note.start < sample);
+
+        // Notes already sounding at the new position are picked up for what is left of them.
+        let held = self.notes[..self.next_note]
             .iter()
-            .filter_map(|instrument| {
-                let gain = (instrument.gain + 30.0) / 30.0;
-                let notes = instrument.notes.get(&current_beat)?;
-                Some(match instrument.name.as_str() {
-                    "sawtooth" => notes
-                        .iter()
-                        .map(|note| {
-                            boxed(GainNode::new(
-                                    PlayedWaveform::new(
-                                        wf!(f32, 44100., sawtooth!(note.frequency())),
-                                        self.samples_per_beat,
-                                    ),
-                                gain,
-                            ))
-                        })
-                        .collect::<Vec<_>>(),
-                    "sine" => notes
-                        .iter()
-                        .map(|note| {
-                            boxed(GainNode::new(
-                                DelayReverb::new(
-                                PlayedWaveform::new(
-                                    wf!(f32, 44100., sine!(note.frequency())),
-                                    self.samples_per_beat,
-                                ),
-                                44100), gain,
-                            ))
-                        })
-                        .collect::<Vec<_>>(),
-                    "square" => notes
-                        .iter()
-                        .map(|note| {
-                            boxed(GainNode::new(
-                                DelayReverb::new(
-                                PlayedWaveform::new(
-                                    wf!(f32, 44100., square!(note.frequency())),
-                                    self.samples_per_beat,
-                                ),
-                                44100), gain,
-                            ))
-                        })
-                        .collect::<Vec<_>>(),
-                    "kick" => notes
-                        .iter()
-                        .map(|_| boxed(GainNode::new(Kick::new(44100), gain)))
-                        .collect::<Vec<_>>(),
-                    _ => todo!(),
-                })
+            .filter(|note| note.start + note.length > sample)
+            .map(|note| ScheduledNote {
+                start: sample,
+                length: note.start + note.length - sample,
+                ..*note
             })
-            .flatten()
             .collect::<Vec<_>>();
+        for note in held {
+            self.play(note);
+        }
+    }
 
-        self.playing_instruments.append(&mut new_instruments);
-
-        self.instruments
-            .iter_mut()
-            .for_each(|instrument| instrument.notes.retain(|beat, _| *beat > current_beat));
+    fn update_state(&mut self) {
+        while let Some(note) = self
+            .notes
+            .get(self.next_note)
+            .filter(|note| note.start <= self.current_sample)
+            .copied()
+        {
+            self.next_note += 1;
+            self.play(note);
+        }
+    }
 
-        self.instruments
-            .retain(|instrument| !instrument.notes.is_empty());
+    fn play(&mut self, note: ScheduledNote) {
+        let channel = &mut self.channels[note.channel];
+        let mut voice = channel.instrument.voice(note.key, note.length);
+        if let Some(filter) = channel.filter {
+            let envelope = Envelope::new(
+                &filter.envelope.unwrap_or_default(),
+                self.sample_rate,
+                note.length,
+            );
+            voice = boxed(FilterNode::new(voice, filter, envelope, self.sample_rate));
+        }
+        if !channel.modulation_routings.is_empty() {
+            let matrix = ModulationMatrix::new(
+                &channel.modulation_routings,
+                note.velocity,
+                note.length,
+                self.sample_rate,
+            );
+            // The channel pans after its effects, this one only follows the modulation.
+            voice = boxed(ModulationNode::new(PanNode::new(voice, 0.0), matrix));
+        }
+        if !channel.effect_routings.is_empty() {
+            channel.effect_modulation = Some(ModulationMatrix::new(
+                &channel.effect_routings,
+                note.velocity,
+                note.length,
+                self.sample_rate,
+            ));
+        }
+        let choke_group = channel.instrument.choke_group(note.key);
+        channel
+            .bus
+            .node_mut()
+            .start(note.key, choke_group, voice, self.elapsed);
     }
 }
 
 fn boxed<T: SoundNode + 'static>(sound: T) -> Box<dyn SoundNode> {
     Box::new(sound)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Constant;
+
+    impl SoundNode for Constant {
+        fn next_frame(&mut self) -> Option<Frame> {
+            Some(Frame::mono(1.0))
+        }
+
+        fn ended(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_pan_constant_power() {
+        // given
+        let mut centre = PanNode::new(Constant, 0.0);
+        let mut left = PanNode::new(Constant, -1.0);
+        let mut half_right = PanNode::new(Constant, 0.5);
+
+        // when
+        let centre = centre.next_frame().unwrap();
+        let left = left.next_frame().unwrap();
+        let half_right = half_right.next_frame().unwrap();
+
+        // then
+        assert!((centre.left - 1.0).abs() < 1e-6 && (centre.right - 1.0).abs() < 1e-6);
+        assert!((left.left - SQRT_2).abs() < 1e-6 && left.right.abs() < 1e-6);
+        let power = half_right.left.powi(2) + half_right.right.powi(2);
+        assert!((power - 2.0).abs() < 1e-5);
+        assert!(half_right.right > half_right.left);
+    }
+
+    fn one_note_box() -> MusicBox {
+        let payload = serde_json::from_value(serde_json::json!({
+            "tempo": 60,
+            "instruments": [{
+                "name": "sine", "gain": 0.0,
+                "notes": [{ "key": "A4", "start": 0, "length": dawlib::TICKS_PER_BEAT }]
+            }]
+        }))
+        .unwrap();
+        MusicBox::new(payload, 8000, &InstrumentRegistry::default()).unwrap()
+    }
+
+    #[test]
+    fn test_seek_skips_earlier_notes() {
+        // given
+        let mut music_box = one_note_box();
+
+        // when
+        music_box.seek(2 * dawlib::TICKS_PER_BEAT);
+        let output = music_box.collect::<Vec<_>>();
+
+        // then
+        assert!(output.len() < 8000);
+        assert!(output.iter().all(|frame| *frame == Frame::default()));
+    }
+
+    #[test]
+    fn test_loop_repeats_until_stopped() {
+        // given
+        let mut music_box = one_note_box();
+
+        // when
+        music_box.repeat(0, 2 * dawlib::TICKS_PER_BEAT);
+        let output = music_box.by_ref().take(5 * 8000).collect::<Vec<_>>();
+
+        // then
+        let loud = |range: std::ops::Range<usize>| {
+            output[range].iter().any(|frame| frame.left.abs() > 0.1)
+        };
+        assert!(loud(0..8000) && loud(16000..24000) && loud(32000..40000));
+        assert!(!loud(10000..16000));
+        assert!(music_box.next().is_some());
+    }
+
+    #[test]
+    fn test_loop_cuts_notes_crossing_its_end() {
+        // given
+        let payload = serde_json::from_value(serde_json::json!({
+            "tempo": 60,
+            "instruments": [{
+                "name": "sawtooth", "gain": 0.0, "effects": [],
+                "notes": [{ "key": "A4", "start": 3 * dawlib::TICKS_PER_BEAT / 2, "length": dawlib::TICKS_PER_BEAT }]
+            }]
+        }))
+        .unwrap();
+        let mut music_box = MusicBox::new(payload, 8000, &InstrumentRegistry::default()).unwrap();
+
+        // when
+        music_box.repeat(0, 2 * dawlib::TICKS_PER_BEAT);
+        let output = music_box.take(3 * 8000).collect::<Vec<_>>();
+
+        // then
+        assert!(output[12000..16000]
+            .iter()
+            .any(|frame| frame.left.abs() > 0.1));
+        assert!(output[16500..24000]
+            .iter()
+            .all(|frame| frame.left.abs() < 1e-3));
+    }
+
+    struct Counted {
+        frames: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        remaining: usize,
+    }
+
+    impl SoundNode for Counted {
+        fn next_frame(&mut self) -> Option<Frame> {
+            self.frames
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.remaining = self.remaining.checked_sub(1)?;
+            Some(Frame::mono(0.5))
+        }
+
+        fn ended(&self) -> bool {
+            self.remaining == 0
+        }
+    }
+
+    struct CountingInstrument {
+        frames: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Instrument for CountingInstrument {
+        fn voice(&self, _key: MidiKey, gate: usize) -> Box<dyn SoundNode> {
+            boxed(Counted {
+                frames: self.frames.clone(),
+                remaining: gate,
+            })
+        }
+    }
+
+    #[test]
+    fn test_render_cost_is_linear_in_note_length() {
+        // Counting the frames asked of the voices, a voice rendered more than once per sample
+        // would show up as work growing faster than the note.
+        fn voice_frames(beats: usize) -> usize {
+            let frames = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let mut registry = InstrumentRegistry::default();
+            let counted = frames.clone();
+            registry.register("counting", move |_, _| {
+                Ok(Box::new(CountingInstrument {
+                    frames: counted.clone(),
+                }))
+            });
+            let payload = serde_json::from_value(serde_json::json!({
+                "tempo": 60,
+                "instruments": [{
+                    "name": "counting", "gain": 0.0,
+                    "notes": [{ "key": "A4", "start": 0, "length": beats * dawlib::TICKS_PER_BEAT }]
+                }]
+            }))
+            .unwrap();
+            MusicBox::new(payload, 8000, &registry).unwrap().count();
+            frames.load(std::sync::atomic::Ordering::Relaxed)
+        }
+
+        // given
+        let (short_beats, long_beats) = (10, 80);
+
+        // when
+        let short = voice_frames(short_beats);
+        let long = voice_frames(long_beats);
+
+        // then
+        assert!(short >= short_beats * 8000 && short <= short_beats * 8000 + 1);
+        assert_eq!(long - short, (long_beats - short_beats) * 8000);
+    }
+}
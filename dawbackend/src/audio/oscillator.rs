@@ -0,0 +1,110 @@
+use std::f32::consts::TAU;
+
+use super::{matrix::Modulation, Frame, SoundNode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveshape {
+    Sine,
+    Sawtooth,
+    Square,
+}
+
+/// Oscillator with a running phase, so every sample costs the same no matter how long the note
+/// is. Sawtooth and square edges are smoothed with PolyBLEP to keep aliasing down.
+pub struct Oscillator {
+    shape: Waveshape,
+    phase: f32,
+    base_increment: f32,
+    increment: f32,
+}
+
+impl Oscillator {
+    pub fn new(shape: Waveshape, frequency: f32, sample_rate: usize) -> Self {
+        Self {
+            shape,
+            phase: 0.0,
+            base_increment: frequency / sample_rate as f32,
+            increment: frequency / sample_rate as f32,
+        }
+    }
+
+    fn value(&self) -> f32 {
+        let (phase, increment) = (self.phase, self.increment);
+        match self.shape {
+            Waveshape::Sine => (phase * TAU).sin(),
+            Waveshape::Sawtooth => 2.0 * phase - 1.0 - poly_blep(phase, increment),
+            Waveshape::Square => {
+                let square = if phase < 0.5 { 1.0 } else { -1.0 };
+                square + poly_blep(phase, increment) - poly_blep((phase + 0.5).fract(), increment)
+            }
+        }
+    }
+}
+
+impl SoundNode for Oscillator {
+    fn next_frame(&mut self) -> Option<Frame> {
+        let value = self.value();
+        self.phase = (self.phase + self.increment).fract();
+        Some(Frame::mono(value))
+    }
+
+    fn ended(&self) -> bool {
+        false
+    }
+
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.increment = self.base_increment * modulation.pitch_ratio();
+    }
+}
+
+/// Correction for a unit step at phase 0, spread over the sample on either side of it.
+fn poly_blep(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let t = phase / increment;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sine_follows_phase() {
+        // given
+        let mut oscillator = Oscillator::new(Waveshape::Sine, 441.0, 44100);
+
+        // when
+        let frames = (0..200)
+            .map(|_| oscillator.next_frame().unwrap().left)
+            .collect::<Vec<_>>();
+
+        // then
+        for (sample, value) in frames.iter().enumerate() {
+            let expected = (sample as f32 / 100.0 * TAU).sin();
+            assert!((value - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_sawtooth_edge_is_smoothed() {
+        // given
+        let mut oscillator = Oscillator::new(Waveshape::Sawtooth, 441.0, 44100);
+
+        // when
+        let frames = (0..101)
+            .map(|_| oscillator.next_frame().unwrap().left)
+            .collect::<Vec<_>>();
+
+        // then
+        assert!(frames.iter().all(|value| value.abs() <= 1.0));
+        assert!((frames[25] + 0.5).abs() < 1e-3);
+        // A naive sawtooth would jump straight to -1 on the wrap.
+        assert!(frames[100].abs() < 0.05);
+    }
+}
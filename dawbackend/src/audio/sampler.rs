@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use dawlib::{EnvelopeDto, InstrumentDto, MidiKey, SamplerParametersDto};
+use hound::{SampleFormat, WavReader};
+
+use super::{
+    boxed,
+    envelope::{Envelope, EnvelopeNode},
+    instrument::Instrument,
+    matrix::Modulation,
+    AudioError, Frame, SoundNode,
+};
+
+pub struct Sample {
+    frames: Vec<Frame>,
+    sample_rate: usize,
+}
+
+impl Sample {
+    fn read(path: &Path) -> Result<Self, hound::Error> {
+        let mut reader = WavReader::open(path)?;
+        let spec = reader.spec();
+
+        let samples = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            SampleFormat::Int => {
+                let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        let frames = samples
+            .chunks_exact(spec.channels.max(1) as usize)
+            .map(|frame| match frame {
+                [left, right, ..] => Frame::new(*left, *right),
+                [mono] => Frame::mono(*mono),
+                [] => Frame::default(),
+            })
+            .collect();
+
+        Ok(Self {
+            frames,
+            sample_rate: spec.sample_rate as usize,
+        })
+    }
+}
+
+/// Loads WAV files from the sample directory, keeping every decoded file for later sessions.
+pub struct SampleLibrary {
+    directory: PathBuf,
+    samples: Mutex<HashMap<PathBuf, Arc<Sample>>>,
+}
+
+impl SampleLibrary {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn load(&self, path: &str) -> Result<Arc<Sample>, AudioError> {
+        let relative_path = Path::new(path);
+        if !relative_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(AudioError::SampleUnavailable(
+                path.to_string(),
+                "path has to be relative to the sample directory".to_string(),
+            ));
+        }
+
+        let full_path = self.directory.join(relative_path);
+        if let Some(sample) = self.samples.lock().unwrap().get(&full_path) {
+            return Ok(sample.clone());
+        }
+
+        let sample = Sample::read(&full_path)
+            .map(Arc::new)
+            .map_err(|error| AudioError::SampleUnavailable(path.to_string(), error.to_string()))?;
+        self.samples
+            .lock()
+            .unwrap()
+            .insert(full_path, sample.clone());
+        Ok(sample)
+    }
+}
+
+pub fn factory(
+    library: Arc<SampleLibrary>,
+) -> impl Fn(&InstrumentDto, usize) -> Result<Box<dyn Instrument>, AudioError> + Send + Sync {
+    move |instrument, sample_rate| {
+        let parameters = instrument
+            .parameters::<SamplerParametersDto>()
+            .map_err(|error| {
+                AudioError::InvalidParameters(
+                    instrument.instrument_type().to_string(),
+                    error.to_string(),
+                )
+            })?;
+
+        if parameters.zones.is_empty() {
+            return Err(AudioError::InvalidParameters(
+                instrument.instrument_type().to_string(),
+                "at least one sample zone is required".to_string(),
+            ));
+        }
+
+        let zones = parameters
+            .zones
+            .iter()
+            .map(|zone| {
+                Ok(Zone {
+                    sample: library.load(&zone.path)?,
+                    root: zone.root,
+                    low: zone.low.unwrap_or(MidiKey::VALUES[0]),
+                    high: zone
+                        .high
+                        .unwrap_or(MidiKey::VALUES[MidiKey::VALUES.len() - 1]),
+                })
+            })
+            .collect::<Result<Vec<_>, AudioError>>()?;
+
+        Ok(Box::new(SamplerInstrument {
+            zones,
+            one_shot: parameters.one_shot,
+            envelope: instrument.envelope,
+            sample_rate,
+        }) as Box<dyn Instrument>)
+    }
+}
+
+struct Zone {
+    sample: Arc<Sample>,
+    root: MidiKey,
+    low: MidiKey,
+    high: MidiKey,
+}
+
+struct SamplerInstrument {
+    zones: Vec<Zone>,
+    one_shot: bool,
+    envelope: EnvelopeDto,
+    sample_rate: usize,
+}
+
+impl SamplerInstrument {
+    fn zone(&self, key: MidiKey) -> &Zone {
+        let distance = |zone: &&Zone| (zone.root as isize - key as isize).abs();
+
+        self.zones
+            .iter()
+            .filter(|zone| zone.low <= key && key <= zone.high)
+            .min_by_key(distance)
+            .or_else(|| self.zones.iter().min_by_key(distance))
+            .expect("sampler without zones")
+    }
+}
+
+impl Instrument for SamplerInstrument {
+    fn voice(&self, key: MidiKey, gate: usize) -> Box<dyn SoundNode> {
+        let zone = self.zone(key);
+        let step = (key.frequency() / zone.root.frequency()) as f64
+            * zone.sample.sample_rate as f64
+            / self.sample_rate as f64;
+        let gate = if self.one_shot { usize::MAX } else { gate };
+
+        boxed(EnvelopeNode::new(
+            SampleNode {
+                sample: zone.sample.clone(),
+                position: 0.0,
+                step,
+                pitch_ratio: 1.0,
+            },
+            Envelope::new(&self.envelope, self.sample_rate, gate),
+        ))
+    }
+}
+
+struct SampleNode {
+    sample: Arc<Sample>,
+    position: f64,
+    step: f64,
+    pitch_ratio: f64,
+}
+
+impl SoundNode for SampleNode {
+    fn next_frame(&mut self) -> Option<Frame> {
+        if self.ended() {
+            return None;
+        }
+
+        let index = self.position as usize;
+        let fraction = (self.position - index as f64) as f32;
+        let current = self.sample.frames[index];
+        let next = self
+            .sample
+            .frames
+            .get(index + 1)
+            .copied()
+            .unwrap_or_default();
+
+        self.position += self.step * self.pitch_ratio;
+        Some(current * (1.0 - fraction) + next * fraction)
+    }
+
+    fn ended(&self) -> bool {
+        self.position as usize >= self.sample.frames.len()
+    }
+
+    fn modulate(&mut self, modulation: &Modulation) {
+        self.pitch_ratio = modulation.pitch_ratio() as f64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_node_repitches_by_octave() {
+        // given
+        let sample = Arc::new(Sample {
+            frames: (0..8).map(|value| Frame::mono(value as f32)).collect(),
+            sample_rate: 44100,
+        });
+        let mut node = SampleNode {
+            sample,
+            position: 0.0,
+            step: (MidiKey::A5.frequency() / MidiKey::A4.frequency()) as f64,
+            pitch_ratio: 1.0,
+        };
+
+        // when
+        let frames = std::iter::from_fn(|| node.next_frame())
+            .map(|frame| frame.left.round())
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(frames, vec![0.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn test_sample_library_rejects_paths_outside_directory() {
+        // given
+        let library = SampleLibrary::new("samples");
+
+        // when
+        let result = library.load("../secret.wav");
+
+        // then
+        assert!(matches!(result, Err(AudioError::SampleUnavailable(..))));
+    }
+}
@@ -1,20 +1,59 @@
-use std::{net::SocketAddr, time::Duration, ops::ControlFlow};
+use std::{net::SocketAddr, time::Duration, ops::{ControlFlow, RangeInclusive}, sync::Arc};
+use tokio::time::Instant;
 use axum::extract::ws::{WebSocket, Message};
-use dawlib::{InstrumentPayloadDto, SoundOutputPacket};
+use dawlib::{StreamRequestDto, SoundOutputPacket, StreamEventDto};
 use futures::{StreamExt, stream::SplitSink, SinkExt};
 use tracing::{error, warn, debug};
 
-use crate::audio::MusicBox;
+use crate::audio::{AudioError, MusicBox, Frame, instrument::InstrumentRegistry};
 
+pub const SUPPORTED_SAMPLE_RATES: RangeInclusive<u32> = 8000..=192000;
+// Chunks sent ahead of the client playback, loops would otherwise be streamed as fast as they render.
+const CHUNKS_AHEAD: u32 = 2;
 
-pub async fn handle_connection(socket: WebSocket, who: SocketAddr) {
+struct Session {
+    sample_rate: Option<usize>,
+    registry: Arc<InstrumentRegistry>,
+    playback: Option<Playback>
+}
+
+/// A song being streamed in chunks of one second.
+struct Playback {
+    music_box: MusicBox,
+    sample_rate: usize,
+    started: Instant,
+    sent_chunks: u32
+}
+
+impl Playback {
+    fn next_chunk_due(&self) -> Instant {
+        self.started + Duration::from_secs(self.sent_chunks.saturating_sub(CHUNKS_AHEAD) as u64)
+    }
+}
+
+pub async fn handle_connection(socket: WebSocket, who: SocketAddr, registry: Arc<InstrumentRegistry>) {
     let (mut sender, mut receiver) = socket.split();
+    let mut session = Session { sample_rate: None, registry, playback: None };
 
     loop {
-        if let Some(msg) = receiver.next().await {
+        // Requests are still read while playing, so a playback can be stopped or replaced.
+        let msg = match session.playback.as_ref().map(Playback::next_chunk_due) {
+            Some(due) => tokio::select! {
+                msg = receiver.next() => msg,
+                _ = tokio::time::sleep_until(due) => {
+                    if stream_chunk(&mut sender, &mut session).await.is_break() {
+                        return;
+                    }
+                    continue;
+                }
+            },
+            None => receiver.next().await
+        };
+
+        if let Some(msg) = msg {
             debug!("Received message.");
             if let Ok(msg) = msg {
-                if process_message(msg, who, &mut sender).await.is_break() {
+                if process_message(msg, who, &mut sender, &mut session).await.is_break() {
                     return;
                 }
             } else {
@@ -28,51 +67,65 @@ pub async fn handle_connection(socket: WebSocket, who: SocketAddr) {
     }
 }
 
-async fn process_message(msg: Message, who: SocketAddr, sender: &mut SplitSink<WebSocket, Message>) -> ControlFlow<(), ()> {
+/// Tells the client why its request was refused, the session stays open for the next one.
+async fn send_error(sender: &mut SplitSink<WebSocket, Message>, message: String) -> ControlFlow<(), ()> {
+    let error = StreamEventDto::Error { message };
+    if sender.send(Message::Text(serde_json::to_string(&error).unwrap())).await.is_err() {
+        return ControlFlow::Break(());
+    }
+    ControlFlow::Continue(())
+}
+
+async fn process_message(msg: Message, who: SocketAddr, sender: &mut SplitSink<WebSocket, Message>, session: &mut Session) -> ControlFlow<(), ()> {
     match msg {
         Message::Text(t) => {
-            if let Ok(payload) = serde_json::from_str::<InstrumentPayloadDto>(&t) {
-                let mut music_box = MusicBox::new(payload.tempo, payload.instruments);
-                let mut index = 0;
-                let mut is_streaming = true;
-                while is_streaming {
-                    let output = match music_box.chunk(44100) {
-                        Ok(full_chunk) => {
-                            debug!("Sending chunk {index}");
-                            index += 1;
-
-                            SoundOutputPacket::Data { 
-                                channel_data: dawlib::ChannelData::Mono(full_chunk) 
-                            }
-                        },
-                        Err(partial_chunk) => {
-                            is_streaming = false;
-                            debug!("Sending end.");
-                            let length = partial_chunk.len() as u16;
-                            let channel_data = if length != 0 {
-                                Some(dawlib::ChannelData::Mono(partial_chunk))
-                            } else {
-                                None
-                            };
-
-                            SoundOutputPacket::End { 
-                                length,
-                                channel_data
-                            }
-                        },
+            match serde_json::from_str::<StreamRequestDto>(&t) {
+                Ok(StreamRequestDto::Open { sample_rate }) => {
+                    if !SUPPORTED_SAMPLE_RATES.contains(&sample_rate) {
+                        warn!(">>> {} requested unsupported sample rate {}", who, sample_rate);
+                        return send_error(sender, format!("Unsupported sample rate {}.", sample_rate)).await;
+                    }
+                    debug!(">>> {} opened session at {} Hz", who, sample_rate);
+                    session.sample_rate = Some(sample_rate as usize);
+                }
+                Ok(StreamRequestDto::Play { payload, start, loop_region }) => {
+                    let Some(sample_rate) = session.sample_rate else {
+                        warn!(">>> {} requested playback before opening a session", who);
+                        return send_error(sender, "Open a session before playing.".to_string()).await;
                     };
 
-                    if sender
-                        .send(Message::Binary(output.into()))
-                        .await
-                        .is_err()
-                    {
-                        return ControlFlow::Break(());
-                    }
+                    session.playback = None;
+                    // Samplers read their files while the music box is built, away from the socket tasks.
+                    let registry = session.registry.clone();
+                    let built = tokio::task::spawn_blocking(move || -> Result<MusicBox, AudioError> {
+                        let mut music_box = MusicBox::new(payload, sample_rate, &registry)?;
+                        music_box.seek(start);
+                        if let Some(region) = loop_region {
+                            music_box.repeat(region.start, region.end);
+                        }
+                        Ok(music_box)
+                    }).await;
+                    let music_box = match built {
+                        Ok(Ok(music_box)) => music_box,
+                        Ok(Err(error)) => {
+                            warn!(">>> {} sent unplayable payload: {}", who, error);
+                            return send_error(sender, error.to_string()).await;
+                        }
+                        Err(error) => {
+                            error!("Building the music box for {} failed: {}", who, error);
+                            return ControlFlow::Break(());
+                        }
+                    };
+                    session.playback = Some(Playback { music_box, sample_rate, started: Instant::now(), sent_chunks: 0 });
+                }
+                Ok(StreamRequestDto::Stop) => {
+                    debug!(">>> {} stopped playback", who);
+                    session.playback = None;
+                }
+                Err(_) => {
+                    warn!(">>> {} sent invalid payload: {:?}", who, t);
+                    return ControlFlow::Break(());
                 }
-            } else {
-                warn!(">>> {} sent invalid payload: {:?}", who, t);
-                return ControlFlow::Break(());
             }
         }
         Message::Binary(d) => {
@@ -99,4 +152,63 @@ async fn process_message(msg: Message, who: SocketAddr, sender: &mut SplitSink<W
         }
     }
     ControlFlow::Continue(())
-}
\ No newline at end of file
+}
+
+async fn stream_chunk(sender: &mut SplitSink<WebSocket, Message>, session: &mut Session) -> ControlFlow<(), ()> {
+    let Some(playback) = session.playback.as_mut() else {
+        return ControlFlow::Continue(());
+    };
+
+    let output = match playback.music_box.chunk(playback.sample_rate) {
+        Ok(full_chunk) => {
+            debug!("Sending chunk {}", playback.sent_chunks);
+            playback.sent_chunks += 1;
+
+            SoundOutputPacket::Data { 
+                channel_data: channel_data(full_chunk)
+            }
+        },
+        Err(partial_chunk) => {
+            debug!("Sending end.");
+            let length = partial_chunk.len() as u32;
+            let channel_data = if length != 0 {
+                Some(channel_data(partial_chunk))
+            } else {
+                None
+            };
+
+            SoundOutputPacket::End { 
+                length,
+                channel_data
+            }
+        },
+    };
+    let clipping = playback.music_box.take_clipping();
+    if matches!(output, SoundOutputPacket::End { .. }) {
+        session.playback = None;
+    }
+
+    if sender
+        .send(Message::Binary(output.into()))
+        .await
+        .is_err()
+    {
+        return ControlFlow::Break(());
+    }
+
+    if let Some(clipping) = clipping {
+        debug!("Chunk clipped {} times, peaking at {:.1} dBTP", clipping.overs, clipping.true_peak);
+        let event = StreamEventDto::Clipping { true_peak: clipping.true_peak, overs: clipping.overs };
+        if sender.send(Message::Text(serde_json::to_string(&event).unwrap())).await.is_err() {
+            return ControlFlow::Break(());
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+fn channel_data(frames: Vec<Frame>) -> dawlib::ChannelData {
+    let (left, right) = frames.into_iter()
+        .map(|frame| (frame.left, frame.right))
+        .unzip();
+    dawlib::ChannelData::Stereo(left, right)
+}
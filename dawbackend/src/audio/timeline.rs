@@ -0,0 +1,252 @@
+use dawlib::{SwingDto, TempoChangeDto, TempoCurve};
+
+// Slower tempos would stretch the beat synced effects over tens of seconds.
+const MIN_TEMPO: f64 = 20.0;
+
+/// Stretch of the song between two points of the tempo map.
+struct Segment {
+    tick: f64,
+    sample: f64,
+    /// In beats per minute, at the start of the segment.
+    tempo: f64,
+    /// Tempo change per tick, zero unless the segment ramps.
+    slope: f64,
+}
+
+impl Segment {
+    fn tempo_at(&self, tick: f64) -> f64 {
+        self.tempo + self.slope * (tick - self.tick)
+    }
+
+    /// `scale` is the length of a tick in samples at one beat per minute.
+    fn sample_at(&self, tick: f64, scale: f64) -> f64 {
+        let ticks = tick - self.tick;
+        if self.slope == 0.0 {
+            self.sample + scale * ticks / self.tempo
+        } else {
+            // The tick length goes with the inverse of the tempo, integrated over the ramp.
+            self.sample + scale / self.slope * (1.0 + self.slope * ticks / self.tempo).ln()
+        }
+    }
+
+    fn tick_at(&self, sample: f64, scale: f64) -> f64 {
+        let samples = sample - self.sample;
+        if self.slope == 0.0 {
+            self.tick + samples * self.tempo / scale
+        } else {
+            self.tick + self.tempo / self.slope * ((self.slope * samples / scale).exp() - 1.0)
+        }
+    }
+}
+
+/// Converts song positions to samples, following the tempo map of the payload.
+pub struct Timeline {
+    segments: Vec<Segment>,
+    scale: f64,
+    sample_rate: usize,
+    resolution: usize,
+}
+
+impl Timeline {
+    /// `tempo` holds from the start of the song until the first change.
+    pub fn new(
+        sample_rate: usize,
+        tempo: f64,
+        changes: &[TempoChangeDto],
+        resolution: usize,
+    ) -> Self {
+        let resolution = resolution.max(1);
+        let scale = sample_rate as f64 * 60.0 / resolution as f64;
+
+        let mut points = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.beat.max(0.0) * resolution as f64,
+                    change.tempo.max(MIN_TEMPO),
+                    change.curve,
+                )
+            })
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.insert(0, (0.0, tempo.max(MIN_TEMPO), TempoCurve::Step));
+
+        let mut segments = Vec::<Segment>::with_capacity(points.len());
+        for (index, (tick, tempo, curve)) in points.iter().copied().enumerate() {
+            let sample = segments
+                .last()
+                .map_or(0.0, |segment| segment.sample_at(tick, scale));
+            let slope = match points.get(index + 1) {
+                Some((next_tick, next_tempo, _))
+                    if curve == TempoCurve::Ramp && *next_tick > tick =>
+                {
+                    (next_tempo - tempo) / (next_tick - tick)
+                }
+                _ => 0.0,
+            };
+            segments.push(Segment {
+                tick,
+                sample,
+                tempo,
+                slope,
+            });
+        }
+
+        Self {
+            segments,
+            scale,
+            sample_rate,
+            resolution,
+        }
+    }
+
+    pub fn sample_at(&self, tick: usize) -> usize {
+        self.position(tick as f64)
+    }
+
+    /// Like [`Timeline::sample_at`], with the off-beats delayed by `swing`.
+    pub fn swung_sample_at(&self, tick: usize, swing: &SwingDto) -> usize {
+        let unit = (self.resolution * 4) as f64 / swing.division.max(1) as f64;
+        let delay = swing.amount.clamp(0.0, 1.0) as f64 * unit / 2.0;
+
+        // The middle of every pair of notes moves late, the positions around it are
+        // stretched or squeezed so the notes keep their order.
+        let pair = (tick as f64 / (2.0 * unit)).floor() * 2.0 * unit;
+        let offset = tick as f64 - pair;
+        let swung = if offset <= unit {
+            offset * (unit + delay) / unit
+        } else {
+            unit + delay + (offset - unit) * (unit - delay) / unit
+        };
+        self.position(pair + swung)
+    }
+
+    fn position(&self, tick: f64) -> usize {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.tick <= tick)
+            .saturating_sub(1);
+        self.segments[index].sample_at(tick, self.scale).round() as usize
+    }
+
+    /// Length of a beat at the given sample, for the effects synced to the tempo.
+    pub fn samples_per_beat_at(&self, sample: usize) -> f64 {
+        let sample = sample as f64;
+        let index = self
+            .segments
+            .partition_point(|segment| segment.sample <= sample)
+            .saturating_sub(1);
+        let segment = &self.segments[index];
+        let tempo = segment.tempo_at(segment.tick_at(sample, self.scale));
+        self.sample_rate as f64 * 60.0 / tempo
+    }
+
+    /// Length of a beat at the slowest tempo of the song.
+    pub fn longest_beat(&self) -> f64 {
+        let slowest = self
+            .segments
+            .iter()
+            .map(|segment| segment.tempo)
+            .fold(f64::INFINITY, f64::min);
+        self.sample_rate as f64 * 60.0 / slowest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timeline_sub_beat_positions() {
+        // given
+        let timeline = Timeline::new(44100, 120.0, &[], 96);
+
+        // when
+        let sixteenth = timeline.sample_at(24);
+        let triplet = timeline.sample_at(32);
+        let bar = timeline.sample_at(4 * 96);
+
+        // then
+        assert_eq!(sixteenth, 5513);
+        assert_eq!(triplet, 7350);
+        assert_eq!(bar, 88200);
+    }
+
+    #[test]
+    fn test_timeline_fractional_tempo_step() {
+        // given
+        let timeline = Timeline::new(
+            44100,
+            120.0,
+            &[TempoChangeDto {
+                beat: 4.0,
+                tempo: 127.5,
+                curve: TempoCurve::Step,
+            }],
+            96,
+        );
+
+        // when
+        let change = timeline.sample_at(4 * 96);
+        let next_bar = timeline.sample_at(8 * 96);
+
+        // then
+        assert_eq!(change, 88200);
+        assert_eq!(next_bar, 88200 + 83012);
+        assert_eq!(timeline.samples_per_beat_at(0), 22050.0);
+        assert!((timeline.samples_per_beat_at(90000) - 20752.94).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_timeline_ramp_accelerates_smoothly() {
+        // given
+        let ramp = |curve| {
+            Timeline::new(
+                48000,
+                60.0,
+                &[
+                    TempoChangeDto {
+                        beat: 0.0,
+                        tempo: 60.0,
+                        curve,
+                    },
+                    TempoChangeDto {
+                        beat: 4.0,
+                        tempo: 120.0,
+                        curve: TempoCurve::Step,
+                    },
+                ],
+                96,
+            )
+        };
+        let (step, ramp) = (ramp(TempoCurve::Step), ramp(TempoCurve::Ramp));
+
+        // when
+        let end = ramp.sample_at(4 * 96);
+
+        // then
+        // Four beats accelerating from one to two beats per second take 4 ln 2 seconds.
+        assert_eq!(end, (48000.0 * 4.0 * 2f64.ln()).round() as usize);
+        assert_eq!(step.sample_at(4 * 96), 4 * 48000);
+        assert!((ramp.samples_per_beat_at(end) - 24000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_swing_delays_off_beats_and_keeps_beats() {
+        // given
+        let timeline = Timeline::new(48000, 60.0, &[], 96);
+        let swing = SwingDto {
+            amount: 0.5,
+            division: 8,
+        };
+
+        // when
+        let samples = [0, 24, 48, 72, 96].map(|tick| timeline.swung_sample_at(tick, &swing));
+
+        // then
+        // The off-beat eighths move late, the sixteenths around them follow so the notes keep their order.
+        assert_eq!([samples[0], samples[4]], [0, 48000]);
+        assert_eq!(samples[2], 30000);
+        assert_eq!([samples[1], samples[3]], [15000, 39000]);
+    }
+}
@@ -0,0 +1,251 @@
+use dawlib::{AutomationParameter, MidiKey, PolyphonyDto, VoiceStealing};
+
+use super::{Frame, SoundNode};
+
+// Stolen and choked voices are faded out over a few milliseconds instead of being cut, which would click.
+const STEAL_FADE_SECONDS: f32 = 0.005;
+// Per-sample decay of the level follower used to find the quietest voice.
+const LEVEL_DECAY: f32 = 0.999;
+
+/// Keeps the voices of one instrument within its polyphony limit.
+pub struct VoiceAllocator {
+    polyphony: PolyphonyDto,
+    voices: Vec<Voice>,
+    fading: Vec<FadeOut>,
+    fade_length: usize,
+}
+
+struct Voice {
+    key: MidiKey,
+    choke_group: Option<usize>,
+    started: usize,
+    level: f32,
+    node: Box<dyn SoundNode>,
+}
+
+struct FadeOut {
+    node: Box<dyn SoundNode>,
+    remaining: usize,
+    length: usize,
+}
+
+impl VoiceAllocator {
+    pub fn new(polyphony: PolyphonyDto, sample_rate: usize) -> Self {
+        Self {
+            polyphony: PolyphonyDto {
+                max_voices: polyphony.max_voices.max(1),
+                ..polyphony
+            },
+            voices: Vec::new(),
+            fading: Vec::new(),
+            fade_length: ((sample_rate as f32 * STEAL_FADE_SECONDS) as usize).max(1),
+        }
+    }
+
+    pub fn start(
+        &mut self,
+        key: MidiKey,
+        choke_group: Option<usize>,
+        node: Box<dyn SoundNode>,
+        current_sample: usize,
+    ) {
+        if choke_group.is_some() {
+            while let Some(index) = self
+                .voices
+                .iter()
+                .position(|voice| voice.choke_group == choke_group)
+            {
+                self.fade_out(index);
+            }
+        }
+
+        if let Some(index) = self.victim(key) {
+            self.fade_out(index);
+        }
+
+        self.voices.push(Voice {
+            key,
+            choke_group,
+            started: current_sample,
+            level: 0.0,
+            node,
+        });
+    }
+
+    /// Fades out every voice, as when playback jumps away from the notes they belong to.
+    pub fn fade_out_all(&mut self) {
+        while let Some(index) = self.voices.len().checked_sub(1) {
+            self.fade_out(index);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty() && self.fading.is_empty()
+    }
+
+    fn fade_out(&mut self, index: usize) {
+        let voice = self.voices.swap_remove(index);
+        self.fading.push(FadeOut {
+            node: voice.node,
+            remaining: self.fade_length,
+            length: self.fade_length,
+        });
+    }
+
+    fn victim(&self, key: MidiKey) -> Option<usize> {
+        if self.polyphony.stealing == VoiceStealing::SameNote {
+            if let Some(index) = self.voices.iter().position(|voice| voice.key == key) {
+                return Some(index);
+            }
+        }
+
+        if self.voices.len() < self.polyphony.max_voices {
+            return None;
+        }
+
+        let voices = self.voices.iter().enumerate();
+        let victim = match self.polyphony.stealing {
+            VoiceStealing::Quietest => voices.min_by(|(_, a), (_, b)| a.level.total_cmp(&b.level)),
+            VoiceStealing::Oldest | VoiceStealing::SameNote => {
+                voices.min_by_key(|(_, voice)| voice.started)
+            }
+        };
+        victim.map(|(index, _)| index)
+    }
+}
+
+impl SoundNode for VoiceAllocator {
+    fn next_frame(&mut self) -> Option<Frame> {
+        let mut frame = Frame::default();
+
+        self.voices.retain_mut(|voice| {
+            if let Some(value) = voice.node.next_frame() {
+                voice.level =
+                    (voice.level * LEVEL_DECAY).max(value.left.abs().max(value.right.abs()));
+                frame += value;
+            }
+            !voice.node.ended()
+        });
+
+        self.fading.retain_mut(|fade| {
+            if let Some(value) = fade.node.next_frame() {
+                frame += value * (fade.remaining as f32 / fade.length as f32);
+            }
+            fade.remaining -= 1;
+            fade.remaining > 0 && !fade.node.ended()
+        });
+
+        Some(frame)
+    }
+
+    fn ended(&self) -> bool {
+        self.is_empty()
+    }
+
+    fn automate(&mut self, parameter: AutomationParameter, value: f32) {
+        for voice in &mut self.voices {
+            voice.node.automate(parameter, value);
+        }
+        for fade in &mut self.fading {
+            fade.node.automate(parameter, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Constant(f32);
+
+    impl SoundNode for Constant {
+        fn next_frame(&mut self) -> Option<Frame> {
+            Some(Frame::mono(self.0))
+        }
+
+        fn ended(&self) -> bool {
+            false
+        }
+    }
+
+    fn allocator(max_voices: usize, stealing: VoiceStealing) -> VoiceAllocator {
+        VoiceAllocator::new(
+            PolyphonyDto {
+                max_voices,
+                stealing,
+            },
+            1000,
+        )
+    }
+
+    fn play(allocator: &mut VoiceAllocator, samples: usize) -> Frame {
+        (0..samples)
+            .map(|_| allocator.next_frame().unwrap())
+            .last()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_oldest_voice_is_stolen() {
+        // given
+        let mut allocator = allocator(2, VoiceStealing::Oldest);
+        allocator.start(MidiKey::C4, None, Box::new(Constant(1.0)), 0);
+        allocator.start(MidiKey::E4, None, Box::new(Constant(2.0)), 1);
+
+        // when
+        allocator.start(MidiKey::G4, None, Box::new(Constant(4.0)), 2);
+        let frame = play(&mut allocator, 10);
+
+        // then
+        assert_eq!(allocator.voices.len(), 2);
+        assert_eq!(frame, Frame::mono(6.0));
+    }
+
+    #[test]
+    fn test_quietest_voice_is_stolen() {
+        // given
+        let mut allocator = allocator(2, VoiceStealing::Quietest);
+        allocator.start(MidiKey::C4, None, Box::new(Constant(1.0)), 0);
+        allocator.start(MidiKey::E4, None, Box::new(Constant(0.5)), 1);
+        play(&mut allocator, 10);
+
+        // when
+        allocator.start(MidiKey::G4, None, Box::new(Constant(4.0)), 2);
+        let frame = play(&mut allocator, 10);
+
+        // then
+        assert_eq!(frame, Frame::mono(5.0));
+    }
+
+    #[test]
+    fn test_choke_group_cuts_playing_voices() {
+        // given
+        let mut allocator = allocator(4, VoiceStealing::Oldest);
+        allocator.start(MidiKey::Bb2, Some(0), Box::new(Constant(1.0)), 0);
+        allocator.start(MidiKey::D1, None, Box::new(Constant(2.0)), 1);
+
+        // when
+        allocator.start(MidiKey::Gb1, Some(0), Box::new(Constant(4.0)), 2);
+        let frame = play(&mut allocator, 10);
+
+        // then
+        assert_eq!(allocator.voices.len(), 2);
+        assert_eq!(frame, Frame::mono(6.0));
+    }
+
+    #[test]
+    fn test_same_note_is_retriggered_below_limit() {
+        // given
+        let mut allocator = allocator(4, VoiceStealing::SameNote);
+        allocator.start(MidiKey::C4, None, Box::new(Constant(1.0)), 0);
+        allocator.start(MidiKey::E4, None, Box::new(Constant(2.0)), 1);
+
+        // when
+        allocator.start(MidiKey::C4, None, Box::new(Constant(4.0)), 2);
+        let frame = play(&mut allocator, 10);
+
+        // then
+        assert_eq!(allocator.voices.len(), 2);
+        assert_eq!(frame, Frame::mono(6.0));
+    }
+}
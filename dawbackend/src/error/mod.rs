@@ -1,6 +1,8 @@
 use axum::{extract::rejection::JsonRejection, http::StatusCode, response::IntoResponse};
 use axum_macros::FromRequest;
 use sea_orm::DbErr;
+
+use crate::audio::AudioError;
 use serde_json::json;
 
 #[derive(FromRequest)]
@@ -40,4 +42,23 @@ impl From<DbErr> for ApiError {
             message: "Unexpected error occured.".to_string() 
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<AudioError> for ApiError {
+    fn from(error: AudioError) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: error.to_string()
+        }
+    }
+}
+
+impl From<hound::Error> for ApiError {
+    fn from(error: hound::Error) -> Self {
+        tracing::error!("Wav Error: {}.", error);
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: "Unexpected error occured.".to_string()
+        }
+    }
+}
@@ -1,14 +1,14 @@
 use axum::{
     extract::{
         ws::{WebSocketUpgrade},
-        TypedHeader,
+        TypedHeader, State,
     },
     response::IntoResponse,
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use tracing::debug;
-use std::{net::SocketAddr, env, error::Error};
+use std::{net::SocketAddr, env, error::Error, sync::Arc};
 use tower_http::{
     trace::{DefaultMakeSpan, TraceLayer}, cors::CorsLayer,
 };
@@ -17,6 +17,7 @@ use axum::extract::connect_info::ConnectInfo;
 
 mod audio;
 mod track;
+mod render;
 mod dal;
 mod error;
 
@@ -42,11 +43,21 @@ async fn main() -> Result<(), Box<dyn Error>>{
         .allow_headers(tower_http::cors::Any)
         .allow_origin(tower_http::cors::Any);
     
-    let state = AppState { database_connection };
+    let samples_directory = env::var("SAMPLES_DIRECTORY").unwrap_or_else(|_| "samples".to_string());
+    let sample_library = Arc::new(audio::sampler::SampleLibrary::new(samples_directory));
+
+    let mut instrument_registry = audio::instrument::InstrumentRegistry::default();
+    instrument_registry.register("sampler", audio::sampler::factory(sample_library));
+
+    let state = AppState {
+        database_connection,
+        instrument_registry: Arc::new(instrument_registry),
+    };
 
     let app = Router::new()
         .route("/ws", get(establish_ws_connection))
         .route("/tracks", get(track::restore).post(track::store))
+        .route("/render", post(render::render))
         .layer(cors)
         .layer(
             TraceLayer::new_for_http()
@@ -65,9 +76,11 @@ async fn main() -> Result<(), Box<dyn Error>>{
 #[derive(Clone)]
 pub struct AppState {
     database_connection: sea_orm::DatabaseConnection,
+    instrument_registry: Arc<audio::instrument::InstrumentRegistry>,
 }
 
 async fn establish_ws_connection(
+    State(state): State<AppState>,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -79,5 +92,5 @@ async fn establish_ws_connection(
     };
     debug!("`{user_agent}` at {addr} connected.");
 
-    ws.on_upgrade(move |socket| audio::streaming::handle_connection(socket, addr))
+    ws.on_upgrade(move |socket| audio::streaming::handle_connection(socket, addr, state.instrument_registry))
 }
\ No newline at end of file
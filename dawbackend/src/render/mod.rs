@@ -0,0 +1,254 @@
+use std::io::Cursor;
+
+use axum::{extract::State, http::{header, StatusCode}, response::IntoResponse};
+use dawlib::{InstrumentPayloadDto, RenderRequestDto, RenderSourceDto, WavFormatDto};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::audio::{Frame, MusicBox, streaming::SUPPORTED_SAMPLE_RATES};
+use crate::error::{JsonInput, ApiError};
+use crate::{AppState, dal::track::TrackRepository};
+
+// Ten minutes at 48 kHz, shorter at higher rates. The file is built in memory before it is sent.
+const MAX_LENGTH_FRAMES: usize = 10 * 60 * 48000;
+// Just below full scale, so the normalised peak survives the conversion to integer samples.
+const NORMALIZED_PEAK: f32 = 0.989;
+
+pub async fn render(State(state): State<AppState>, JsonInput(request): JsonInput<RenderRequestDto>) -> Result<impl IntoResponse, ApiError> {
+    if !SUPPORTED_SAMPLE_RATES.contains(&request.sample_rate) {
+        return Err(ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("Unsupported sample rate {}.", request.sample_rate),
+        });
+    }
+
+    let payload = match request.source {
+        RenderSourceDto::Payload { payload } => payload,
+        RenderSourceDto::Track { track } => stored_track(&state, &track).await?,
+    };
+
+    // Rendering runs far ahead of real time and keeps a core busy, away from the request handlers.
+    let registry = state.instrument_registry.clone();
+    let wav = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, ApiError> {
+        let music_box = MusicBox::new(payload, request.sample_rate as usize, &registry)?;
+        render_wav(music_box, request.sample_rate, request.format, request.normalize, request.tail, MAX_LENGTH_FRAMES)
+    })
+    .await
+    .map_err(|error| {
+        tracing::error!("Render task failed: {}", error);
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: "Unexpected error occured.".to_string(),
+        }
+    })??;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "audio/wav"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"render.wav\""),
+        ],
+        wav,
+    ))
+}
+
+/// Encodes the notes of the song to the end of their release, and the decay of the effects with `tail`,
+/// up to `max_length` frames.
+/// The frames are written as they are rendered, unless they are normalised to the peak of the song.
+fn render_wav(
+    music_box: MusicBox,
+    sample_rate: u32,
+    format: WavFormatDto,
+    normalize_peak: bool,
+    tail: bool,
+    max_length: usize,
+) -> Result<Vec<u8>, ApiError> {
+    let too_long = || ApiError {
+        status: StatusCode::BAD_REQUEST,
+        message: format!("The render is longer than {} seconds at {} Hz.", max_length / sample_rate as usize, sample_rate),
+    };
+    let notes_end = music_box.notes_end();
+    if notes_end > max_length {
+        return Err(too_long());
+    }
+
+    // Releases and tails may ring on past the limit, a frame left over tells.
+    let frames: Box<dyn Iterator<Item = Frame>> = if tail {
+        Box::new(music_box)
+    } else {
+        Box::new(music_box.until_voices_end())
+    };
+    let mut frames = frames.take(max_length + 1);
+    let wav = if normalize_peak {
+        let mut buffered = frames.by_ref().take(max_length).collect::<Vec<_>>();
+        normalize(&mut buffered);
+        encode(buffered, sample_rate, format)?
+    } else {
+        encode(frames.by_ref().take(max_length), sample_rate, format)?
+    };
+    if frames.next().is_some() {
+        return Err(too_long());
+    }
+    Ok(wav)
+}
+
+async fn stored_track(state: &AppState, name: &str) -> Result<InstrumentPayloadDto, ApiError> {
+    let model = TrackRepository::find_by_name(&state.database_connection, name).await?;
+
+    match model.map(|model| serde_json::from_value(model.data)) {
+        Some(Ok(payload)) => Ok(payload),
+        None => {
+            Err(ApiError {
+                status: StatusCode::NOT_FOUND,
+                message: "Track not found.".to_string(),
+            })
+        },
+        Some(Err(other_error)) => {
+            tracing::error!("Error occured {}", other_error);
+            Err(ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: format!("Unexpected error: {}.", other_error),
+            })
+        },
+    }
+}
+
+fn normalize(frames: &mut [Frame]) {
+    let peak = frames.iter()
+        .map(|frame| frame.left.abs().max(frame.right.abs()))
+        .fold(0.0, f32::max);
+    if peak > 0.0 {
+        let gain = NORMALIZED_PEAK / peak;
+        for frame in frames {
+            *frame = *frame * gain;
+        }
+    }
+}
+
+fn encode(frames: impl IntoIterator<Item = Frame>, sample_rate: u32, format: WavFormatDto) -> Result<Vec<u8>, hound::Error> {
+    let (bits_per_sample, sample_format) = match format {
+        WavFormatDto::Int16 => (16, SampleFormat::Int),
+        WavFormatDto::Int24 => (24, SampleFormat::Int),
+        WavFormatDto::Float32 => (32, SampleFormat::Float),
+    };
+    let spec = WavSpec { channels: 2, sample_rate, bits_per_sample, sample_format };
+
+    let mut output = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut output, spec)?;
+    let full_scale = ((1i64 << (bits_per_sample - 1)) - 1) as f32;
+    for sample in frames.into_iter().flat_map(|frame| [frame.left, frame.right]) {
+        match format {
+            WavFormatDto::Int16 => writer.write_sample((sample.clamp(-1.0, 1.0) * full_scale).round() as i16)?,
+            WavFormatDto::Int24 => writer.write_sample((sample.clamp(-1.0, 1.0) * full_scale).round() as i32)?,
+            WavFormatDto::Float32 => writer.write_sample(sample)?,
+        }
+    }
+    writer.finalize()?;
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::body::HttpBody;
+    use hound::WavReader;
+
+    use super::*;
+    use crate::audio::instrument::InstrumentRegistry;
+
+    /// Sine instruments of older payloads play through a reverb, sawtooth ones are dry.
+    fn one_beat_song(instrument: &str) -> InstrumentPayloadDto {
+        serde_json::from_value(serde_json::json!({
+            "tempo": 60,
+            "instruments": [{
+                "name": instrument, "gain": 0.0,
+                "notes": [{ "key": "A4", "start": 0, "length": dawlib::TICKS_PER_BEAT }]
+            }]
+        })).unwrap()
+    }
+
+    fn music_box() -> MusicBox {
+        MusicBox::new(one_beat_song("sine"), 8000, &InstrumentRegistry::default()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_render_covers_the_notes() -> Result<(), hound::Error> {
+        // given
+        let state = AppState {
+            database_connection: Default::default(),
+            instrument_registry: Arc::new(InstrumentRegistry::default()),
+        };
+        let request = RenderRequestDto {
+            source: RenderSourceDto::Payload { payload: one_beat_song("sawtooth") },
+            sample_rate: 8000,
+            format: WavFormatDto::Float32,
+            normalize: false,
+            tail: false
+        };
+
+        // when
+        let response = render(State(state), JsonInput(request)).await.into_response();
+
+        // then
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body();
+        let mut wav = Vec::new();
+        while let Some(chunk) = body.data().await {
+            wav.extend_from_slice(&chunk.unwrap());
+        }
+        let mut reader = WavReader::new(Cursor::new(wav))?;
+        assert!(reader.duration() as usize > music_box().notes_end());
+        // The release of the last note plays out instead of being cut.
+        let samples = reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?;
+        assert!(samples[samples.len() - 2..].iter().all(|sample| sample.abs() < 1e-3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_length_is_capped() {
+        // given
+        let length = music_box().until_voices_end().count();
+        let render = |tail, max_length| render_wav(music_box(), 8000, WavFormatDto::Int16, false, tail, max_length);
+
+        // when
+        let whole = render(false, length);
+        let cut = render(false, length - 1);
+        let ringing = render(true, length);
+
+        // then
+        let duration = whole.ok().and_then(|wav| WavReader::new(Cursor::new(wav)).ok()).map(|reader| reader.duration());
+        assert_eq!(duration, Some(length as u32));
+        assert!(cut.is_err());
+        assert!(ringing.is_err());
+    }
+
+    #[test]
+    fn test_encode_24_bit_keeps_full_scale() -> Result<(), hound::Error> {
+        // given
+        let frames = [Frame::new(1.0, -1.0), Frame::new(2.0, 0.5)];
+
+        // when
+        let wav = encode(frames, 48000, WavFormatDto::Int24)?;
+
+        // then
+        let mut reader = WavReader::new(Cursor::new(wav))?;
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        assert_eq!(reader.spec().sample_rate, 48000);
+        let samples = reader.samples::<i32>().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(samples, vec![8388607, -8388607, 8388607, 4194304]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_scales_peak() {
+        // given
+        let mut frames = [Frame::new(0.25, -0.5), Frame::mono(0.1)];
+
+        // when
+        normalize(&mut frames);
+
+        // then
+        assert!((frames[0].right + NORMALIZED_PEAK).abs() < 1e-6);
+        assert!((frames[1].left - NORMALIZED_PEAK / 5.0).abs() < 1e-6);
+    }
+}
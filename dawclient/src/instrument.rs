@@ -8,11 +8,13 @@ use yew_hooks::prelude::*;
 use yewdux::prelude::*;
 use std::rc::Rc;
 
-use dawlib::{MidiKey, InstrumentPayloadDto, InstrumentDto};
+use dawlib::{MidiKey, InstrumentPayloadDto, InstrumentDto, EnvelopeDto, NoteDto, PolyphonyDto, FilterDto, EffectDto, SendDto, BusDto, ModulationRoutingDto, AutomationLaneDto, AutomationPointDto, MasterDto, TempoChangeDto, TimeSignatureDto, SwingDto, Meter, LoopRegionDto, TICKS_PER_BEAT, MAX_VELOCITY};
 
 use crate::{context_panel::ContextPanelStore, document::hooks::*};
 
 const BEAT_COUNT: usize = 300;
+const STEPS_PER_BEAT: usize = 4;
+const TICKS_PER_STEP: usize = TICKS_PER_BEAT / STEPS_PER_BEAT;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MidiFragment {
@@ -107,11 +109,42 @@ pub fn project_component() -> Html {
         });
     }
 
+    let track_state = use_store_value::<TrackState>();
+    let meter = Meter::new(&track_state.time_signatures, TICKS_PER_BEAT);
     let beat_handle = html! {
         <div onpointerdown={on_pointer_down} class="h-full">
         {
             for state.current().iter().map(|element| {
-                html! { <div class="inline-block w-32 text-right h-full pr-1 py-1 border-r border-gray-600">{ element }</div> }
+                let tempo_change = track_state.tempo_changes.iter()
+                    .rfind(|change| change.beat as usize == *element)
+                    .map(|change| html! { <span class="float-left pl-1 text-teal-300"> {format!("{} BPM", change.tempo)} </span> });
+                let (bar, beat) = meter.position_at(element * TICKS_PER_BEAT);
+                let position = if beat == 0 {
+                    html! { <span class="font-bold">{ bar + 1 }</span> }
+                } else {
+                    html! { <span class="font-normal">{ format!("{}.{}", bar + 1, beat + 1) }</span> }
+                };
+                // Double click moves the playback start there, with alt it loops from the start up to this beat.
+                let element = *element;
+                let on_double_click = Dispatch::<TrackState>::new().reduce_mut_callback_with(move |state, event: MouseEvent| {
+                    let tick = element * TICKS_PER_BEAT;
+                    if event.alt_key() {
+                        state.loop_region = (tick + TICKS_PER_BEAT > state.play_start)
+                            .then_some(LoopRegionDto { start: state.play_start, end: tick + TICKS_PER_BEAT });
+                    } else {
+                        state.play_start = tick;
+                        state.loop_region = None;
+                    }
+                });
+                let tick = element * TICKS_PER_BEAT;
+                let looped = track_state.loop_region.is_some_and(|region| region.start <= tick && tick < region.end);
+                let start_marker = (track_state.play_start / TICKS_PER_BEAT == element)
+                    .then(|| html! { <span class="float-left pl-1 text-teal-300"> {"⏵"} </span> });
+                html! {
+                    <div ondblclick={on_double_click} class={classes!("inline-block", "w-32", "text-right", "h-full", "pr-1", "py-1", "border-r", "border-gray-600", looped.then_some("bg-gray-700"))}>
+                        { for start_marker }{ for tempo_change }{ position }
+                    </div>
+                }
             })
         }
         </div>
@@ -135,20 +168,40 @@ pub fn project_component() -> Html {
 
 #[derive(Debug, Clone, PartialEq, Store)]
 pub struct TrackState {
-    pub tempo: usize,
+    pub tempo: f64,
+    pub tempo_changes: Vec<TempoChangeDto>,
+    pub time_signatures: Vec<TimeSignatureDto>,
+    pub swing: SwingDto,
+    /// Tick playback starts from.
+    pub play_start: usize,
+    pub loop_region: Option<LoopRegionDto>,
     pub entries: HashMap<String, InstrumentData>,
+    pub buses: Vec<BusDto>,
+    pub master: MasterDto,
 }
 
 
 #[derive(Debug, Default, Clone, PartialEq, Store)]
 pub struct InstrumentData {
+    pub instrument_type: Option<String>,
     pub gain: f32,
-    pub notes: HashMap<usize, Vec<MidiKey>>
+    pub pan: f32,
+    pub notes: Vec<NoteDto>,
+    pub envelope: EnvelopeDto,
+    pub polyphony: PolyphonyDto,
+    pub filter: Option<FilterDto>,
+    pub effects: Vec<EffectDto>,
+    pub output: Option<String>,
+    pub sends: Vec<SendDto>,
+    pub modulation_routings: Vec<ModulationRoutingDto>,
+    pub automation: Vec<AutomationLaneDto>,
+    pub swing: Option<SwingDto>,
+    pub parameters: serde_json::Value
 }
 
 impl Default for TrackState {
     fn default() -> Self {
-        Self { tempo: 60, entries: HashMap::new() }
+        Self { tempo: 60.0, tempo_changes: Vec::new(), time_signatures: Vec::new(), swing: SwingDto::default(), play_start: 0, loop_region: None, entries: HashMap::new(), buses: Vec::new(), master: MasterDto::default() }
     }
 }
 
@@ -158,28 +211,77 @@ impl From<TrackState> for InstrumentPayloadDto {
         .map(|(instrument, data)| {
             InstrumentDto {
                 name: instrument,
+                instrument_type: data.instrument_type,
                 gain: data.gain,
-                notes: data.notes
+                pan: data.pan,
+                notes: data.notes,
+                envelope: data.envelope,
+                polyphony: data.polyphony,
+                filter: data.filter,
+                effects: data.effects,
+                output: data.output,
+                sends: data.sends,
+                modulation_routings: data.modulation_routings,
+                automation: data.automation,
+                swing: data.swing,
+                parameters: data.parameters
             }
         }).collect();
 
-        InstrumentPayloadDto { tempo: state.tempo, instruments }
+        InstrumentPayloadDto { tempo: state.tempo, tempo_changes: state.tempo_changes, time_signatures: state.time_signatures, swing: state.swing, resolution: TICKS_PER_BEAT, instruments, buses: state.buses, master: state.master }
     }
 }
 
 impl From<InstrumentPayloadDto> for TrackState {
     fn from(payload: InstrumentPayloadDto) -> Self {
+        let resolution = payload.resolution;
+        let meter = Meter::new(&payload.time_signatures, resolution);
         let entries = payload.instruments.into_iter()
         .map(|instrument| {
+            let notes = instrument.notes.into_iter()
+                .map(|note| NoteDto {
+                    bar: None,
+                    start: note.start_tick(&meter) * TICKS_PER_BEAT / resolution,
+                    length: note.length * TICKS_PER_BEAT / resolution,
+                    ..note
+                })
+                .collect();
+            let automation = instrument.automation.into_iter()
+                .map(|lane| AutomationLaneDto {
+                    points: lane.points.into_iter()
+                        .map(|point| AutomationPointDto { tick: point.tick * TICKS_PER_BEAT / resolution, ..point })
+                        .collect(),
+                    ..lane
+                })
+                .collect();
             (instrument.name, InstrumentData {
+                instrument_type: instrument.instrument_type,
                 gain: instrument.gain,
-                notes: instrument.notes
+                pan: instrument.pan,
+                notes,
+                envelope: instrument.envelope,
+                polyphony: instrument.polyphony,
+                filter: instrument.filter,
+                effects: instrument.effects,
+                output: instrument.output,
+                sends: instrument.sends,
+                modulation_routings: instrument.modulation_routings,
+                automation,
+                swing: instrument.swing,
+                parameters: instrument.parameters
             })
         }).collect();
 
         TrackState {
             tempo: payload.tempo,
-            entries
+            tempo_changes: payload.tempo_changes,
+            time_signatures: payload.time_signatures,
+            swing: payload.swing,
+            play_start: 0,
+            loop_region: None,
+            entries,
+            buses: payload.buses,
+            master: payload.master
         }
     }
 }
@@ -243,8 +345,13 @@ pub fn instrument_component(props: &InstrumentComponentProperties) -> Html {
                 <MidiFragmentContextComponent fragment={fragment} name={instrument} />
             }))
         });
-        if let Some(notes) = track_state.entries.get(props.name).and_then(|instrument_entry| instrument_entry.notes.get(&element)) {
-            let notes = notes.iter().map(|note| format!("{} ", note.name())).collect::<String>();
+        let notes = track_state.entries.get(props.name)
+            .map(|instrument_entry| instrument_entry.notes.iter()
+                .filter(|note| note.start / TICKS_PER_BEAT == element)
+                .map(|note| format!("{} ", note.key.name()))
+                .collect::<String>())
+            .unwrap_or_default();
+        if !notes.is_empty() {
             html! { 
                 <div class="inline-block w-32 pr-1 text-xs border-r border-gray-600 h-full overflow-hidden hover:bg-color-gray-600" onclick={on_click}>
                     <span class="h-full"> {notes} </span>
@@ -260,6 +367,7 @@ pub fn instrument_component(props: &InstrumentComponentProperties) -> Html {
                 <div class="pl-4 w-36 border-r border-gray-600 instrument">
                     <p class="text-xs"> {props.name.capitalize()} </p>
                     <GainComponent instrument_name={props.name}/>
+                    <PanComponent instrument_name={props.name}/>
                 </div>
                 <div class="grow box-border text-white border-box bg-gray-700 instrument-timeline-scroll h-full overflow-x-scroll overflow-y-hidden  whitespace-nowrap scrollbar-hide">
                     {timeline}
@@ -310,6 +418,45 @@ pub fn gain_component(props: &VolumeComponentProperties) -> Html{
 }
 
 
+#[function_component(PanComponent)]
+pub fn pan_component(props: &VolumeComponentProperties) -> Html {
+    let component_id = format!("{}PanSlider", props.instrument_name);
+    let (track_state, track_dispatch) = use_store::<TrackState>();
+
+    let on_input = {
+        let instrument_name = props.instrument_name;
+        track_dispatch.reduce_mut_callback_with(
+            move |state, event: yew::events::InputEvent| {
+                let input = event.target().and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+
+                if let Some(input) = input {
+                    state.entries.entry(instrument_name.to_string()).or_default().pan = input.value().parse::<f32>().unwrap() / 100.0f32;
+                }
+            }
+        )
+    };
+
+    let pan_value = track_state.entries.get(props.instrument_name).map(|data| data.pan).unwrap_or_default();
+    let input_value = (pan_value * 100.0f32).round() as i32;
+    let pan_label = match input_value {
+        0 => "C".to_string(),
+        value if value < 0 => format!("L{}", -value),
+        value => format!("R{value}"),
+    };
+    html! {
+        <div>
+            <label for={component_id.clone()} class="inline-block text-neutral-200 text-xs">{"Pan"}</label>
+            <div class="flex h-7">
+                <input type="range" min="-100" max="100" oninput={on_input} value={input_value.to_string()} class="outline-0 flex-grow h-7 accent-blue-400 transparent h-1.5 w-full cursor-pointer rounded-lg border-none" id={component_id} />
+                <div class="bg-transparent ml-1 text-xs text-white py-0 px-1 rounded h-7 text-center w-[68px]">
+                    <span class="inline-block pt-1 select-none"> { pan_label } </span>
+                </div>
+            </div>
+        </div>
+    }
+}
+
+
 #[derive(Debug, Clone, PartialEq, Eq, Properties)]
 pub struct PianoRollComponentProperties {
     pub instrument_name: AttrValue
@@ -358,25 +505,25 @@ pub fn piano_roll_key(props: &PianoRollKeyComponentProperties) -> Html {
         let midi_key = midi_key;
         let dispatch = Dispatch::<TrackState>::new();
         let instrument_name = props.instrument_name.clone();
+        let start = index * TICKS_PER_STEP;
         let on_click = dispatch.reduce_mut_callback(move |state| {
             let instrument = state.entries.entry(instrument_name.to_string()).or_default();
-            let keys = instrument.notes.entry(index).or_default();
-            if keys.contains(&midi_key) {
-                let mut filtered_keys = keys.iter()
-                    .filter(|key| **key != midi_key)
-                    .copied()
-                    .collect::<Vec<MidiKey>>();
-                keys.clear();
-                keys.append(&mut filtered_keys);   
-            } else {
-                keys.push(midi_key);
+            let note_count = instrument.notes.len();
+            instrument.notes.retain(|note| note.key != midi_key || note.start != start);
+            if instrument.notes.len() == note_count {
+                instrument.notes.push(NoteDto {
+                    key: midi_key,
+                    bar: None,
+                    start,
+                    length: TICKS_PER_STEP,
+                    velocity: MAX_VELOCITY
+                });
                 log!("Hello");
             }
         });
 
-        let is_set = instrument_state.notes.get(&index)
-            .filter(|keys| keys.contains(&midi_key))
-            .is_some();
+        let is_set = instrument_state.notes.iter()
+            .any(|note| note.key == midi_key && note.start == start);
 
 
 
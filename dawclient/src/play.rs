@@ -1,6 +1,6 @@
 use std::{rc::Rc, sync::{RwLock}};
 
-use gloo_console::{__macro::JsValue, log};
+use gloo_console::{__macro::JsValue, log, warn};
 use wasm_bindgen::{prelude::Closure, JsCast};
 use web_sys::{AudioBufferSourceNode, AudioNode, AudioContext};
 
@@ -170,6 +170,14 @@ pub fn play_button() -> Html {
                     audio_streamer.write().unwrap().play_chunk(chunk).unwrap();
                     audio_streamer_state.set(audio_streamer.read().unwrap().state());
                 }
+                AudioStreamingWorkerOutput::Error(message) => {
+                    log!("Playback failed: ", message);
+                    audio_streamer.write().unwrap().stop().unwrap();
+                    audio_streamer_state.set(audio_streamer.read().unwrap().state());
+                }
+                AudioStreamingWorkerOutput::Clipping { true_peak, overs } => {
+                    warn!(format!("Output clipped: {overs} samples over 0 dBFS, true peak {true_peak:.1} dBTP"));
+                }
                 AudioStreamingWorkerOutput::End => {
                     let audio_streamer_state = audio_streamer_state.clone();
                     let audio_streamer_handle = audio_streamer.clone();
@@ -182,9 +190,18 @@ pub fn play_button() -> Html {
         })
     };
 
+    {
+        let worker_bridge = worker_bridge.clone();
+        let sample_rate = audio_streamer.read().unwrap().ctx.sample_rate();
+        use_effect_with_deps(move |_| {
+            worker_bridge.send(AudioStreamingWorkerInput::Open { sample_rate: sample_rate as u32 });
+        }, ());
+    }
+
     match *audio_streamer_state {
         StreamerState::Playing => {
             let stop = move |_| {
+                worker_bridge.send(AudioStreamingWorkerInput::Stop);
                 audio_streamer.write().unwrap().stop().unwrap();
                 audio_streamer_state.set(audio_streamer.read().unwrap().state());
             };
@@ -195,9 +212,11 @@ pub fn play_button() -> Html {
         StreamerState::Waiting => {
             let play = move |_| {
                 audio_streamer.write().unwrap().play();
-                worker_bridge.send(AudioStreamingWorkerInput::SendInstrument(
-                    instruments.as_ref().clone().into()
-                ));
+                worker_bridge.send(AudioStreamingWorkerInput::Play {
+                    payload: instruments.as_ref().clone().into(),
+                    start: instruments.play_start,
+                    loop_region: instruments.loop_region
+                });
             };
             html! {
                 <button class={format!("outline-0 bg-transparent text-white font-semibold py-0 px-1 border border-gray-500 rounded h-7 w-7 hover:bg-gray-500 hover:border-transparent")} onclick={play}> {"⏵"} </button>
@@ -16,8 +16,6 @@ pub fn tempo_component() -> Html {
                     input.set_value(&220.to_string()); 
                 } else if value < 0.0 {
                     input.set_value(&20.to_string()); 
-                } else {
-                    input.set_value(&(value as usize).to_string());
                 }
             } else {
                 let value = input.value().chars().filter(|char| *char != '-')
@@ -31,21 +29,21 @@ pub fn tempo_component() -> Html {
 
     let on_change = track_dispatcher.reduce_mut_callback_with(move |state, event: Event| {
         if let Some(input) = event.target().as_ref().and_then(|target| target.dyn_ref::<HtmlInputElement>()) {
-            if let Ok(value) = input.value().parse::<usize>() {
-                if value >= 20 {
+            if let Ok(value) = input.value().parse::<f64>() {
+                if value >= 20.0 {
                     state.tempo = value;
                 } else {
-                    state.tempo = 20;
+                    state.tempo = 20.0;
                 }
             } else {
-                state.tempo = 20;
+                state.tempo = 20.0;
             }
         } 
     });
 
     html! {
         <div class="block bg-transparent text-xs text-white py-0 px-1 border border-gray-500 rounded h-7 text-center">  
-            <input type="number" class="ml-1 outline-0 inline-block mt-1 w-5 bg-transparent text-xs" oninput={on_input} onchange={on_change} value={track_state.tempo.to_string()} />
+            <input type="number" step="0.5" class="ml-1 outline-0 inline-block mt-1 w-8 bg-transparent text-xs" oninput={on_input} onchange={on_change} value={track_state.tempo.to_string()} />
             <span class="inline-block ml-1 pt-1 mr-1 select-none"> {"BPM"} </span>
         </div>
     }
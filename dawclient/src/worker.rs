@@ -1,6 +1,6 @@
-use std::{collections::HashSet, rc::Rc};
+use std::{cell::Cell, collections::HashSet, rc::Rc};
 
-use dawlib::{InstrumentPayloadDto, SoundOutputPacket};
+use dawlib::{InstrumentPayloadDto, LoopRegionDto, SoundOutputPacket, StreamEventDto, StreamRequestDto};
 use futures::{StreamExt, stream::SplitSink, SinkExt, lock::Mutex};
 use gloo_net::websocket::{Message, futures::WebSocket};
 use serde::{Serialize, Deserialize};
@@ -11,18 +11,23 @@ use gloo_console::log;
 pub struct AudioStreamingWorker {
     _link: WorkerLink<Self>,
     write_socket: Rc<Mutex<SplitSink<WebSocket, gloo_net::websocket::Message>>>,
-    listeners: Rc<Mutex<HashSet<HandlerId>>>
+    listeners: Rc<Mutex<HashSet<HandlerId>>>,
+    sample_rate: Rc<Cell<usize>>
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum AudioStreamingWorkerInput {
-    SendInstrument(InstrumentPayloadDto)
+    Open { sample_rate: u32 },
+    Play { payload: InstrumentPayloadDto, start: usize, loop_region: Option<LoopRegionDto> },
+    Stop
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum AudioStreamingWorkerOutput {
     Chunk(Vec<Vec<f32>>),
-    End
+    End,
+    Error(String),
+    Clipping { true_peak: f32, overs: usize }
 }
 
 impl yew_agent::Worker for AudioStreamingWorker {
@@ -35,20 +40,32 @@ impl yew_agent::Worker for AudioStreamingWorker {
         let ws = WebSocket::open("ws://localhost:3000/ws").unwrap();
         let (write_socket, mut read_socket) = ws.split();
         let listeners = Rc::new(Mutex::new(HashSet::new()));
+        let sample_rate = Rc::new(Cell::new(0));
         
         {
             let link = link.clone();
             let listeners = listeners.clone();
+            let sample_rate = sample_rate.clone();
             spawn_local(async move {
                 while let Some(msg) = read_socket.next().await {
                     match msg {
                         Ok(msg) => {
                             match msg {
                                 gloo_net::websocket::Message::Text(value) => {
-                                    log!(format!("Text: {:#?}", value));
+                                    let output = match serde_json::from_str::<StreamEventDto>(&value) {
+                                        Ok(StreamEventDto::Error { message }) => AudioStreamingWorkerOutput::Error(message),
+                                        Ok(StreamEventDto::Clipping { true_peak, overs }) => AudioStreamingWorkerOutput::Clipping { true_peak, overs },
+                                        Err(_) => {
+                                            log!(format!("Text: {:#?}", value));
+                                            continue;
+                                        }
+                                    };
+                                    for listener in listeners.lock().await.iter() {
+                                        link.respond(*listener, output.clone())
+                                    }
                                 }
                                 gloo_net::websocket::Message::Bytes(bytes) => {
-                                    let sound = SoundOutputPacket::try_from((bytes, 44100)).expect("Sumfin went rong");
+                                    let sound = SoundOutputPacket::try_from((bytes, sample_rate.get())).expect("Sumfin went rong");
                                     match sound {
                                         SoundOutputPacket::End { channel_data, ..} => {
                                             if let Some(channel_data) = channel_data {
@@ -97,7 +114,7 @@ impl yew_agent::Worker for AudioStreamingWorker {
         }
         
 
-        Self { _link: link, write_socket: Rc::new(Mutex::new(write_socket)), listeners }
+        Self { _link: link, write_socket: Rc::new(Mutex::new(write_socket)), listeners, sample_rate }
     }
 
     fn connected(&mut self, id: HandlerId) {
@@ -119,17 +136,22 @@ impl yew_agent::Worker for AudioStreamingWorker {
     }
 
     fn handle_input(&mut self, msg: Self::Input, _id: HandlerId) {
-        match msg {
-            AudioStreamingWorkerInput::SendInstrument(payload) => {
-                let write_socket = self.write_socket.clone();
-
-                spawn_local(async move {
-                    write_socket.lock().await.send(Message::Text(serde_json::to_string(&payload).unwrap())).await.unwrap();
-                    log!("Sent Something")
-                });
-                
+        let request = match msg {
+            AudioStreamingWorkerInput::Open { sample_rate } => {
+                self.sample_rate.set(sample_rate as usize);
+                StreamRequestDto::Open { sample_rate }
             },
-        }
+            AudioStreamingWorkerInput::Play { payload, start, loop_region } => {
+                StreamRequestDto::Play { payload, start, loop_region }
+            },
+            AudioStreamingWorkerInput::Stop => StreamRequestDto::Stop,
+        };
+
+        let write_socket = self.write_socket.clone();
+        spawn_local(async move {
+            write_socket.lock().await.send(Message::Text(serde_json::to_string(&request).unwrap())).await.unwrap();
+            log!("Sent Something")
+        });
     }
 
     fn name_of_resource() -> &'static str {
@@ -1,28 +1,1010 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, num::ParseIntError};
 
 use itertools::Itertools;
 use reqwest::Response;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
 dawmacros::generate_keys!();
 
+/// Resolution of the note grid in ticks per quarter note, assumed for payloads that do not state one.
+pub const TICKS_PER_BEAT: usize = 96;
+
+/// Velocity of notes played at full strength, the default for notes that do not state one.
+pub const MAX_VELOCITY: u8 = 127;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "InstrumentPayloadRepr")]
 pub struct InstrumentPayloadDto {
-    pub tempo: usize,
-    pub instruments: Vec<InstrumentDto>
+    /// Beats per minute from the start of the song until the first tempo change, tempos below 20
+    /// play at 20.
+    pub tempo: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tempo_changes: Vec<TempoChangeDto>,
+    /// Songs start in 4/4 unless a signature is given for the first bar.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub time_signatures: Vec<TimeSignatureDto>,
+    /// Applies to the instruments that do not set their own.
+    #[serde(default)]
+    pub swing: SwingDto,
+    #[serde(default = "default_resolution")]
+    pub resolution: usize,
+    pub instruments: Vec<InstrumentDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buses: Vec<BusDto>,
+    #[serde(default)]
+    pub master: MasterDto
+}
+
+fn default_resolution() -> usize {
+    TICKS_PER_BEAT
+}
+
+#[derive(Deserialize)]
+struct InstrumentPayloadRepr {
+    tempo: f64,
+    #[serde(default)]
+    tempo_changes: Vec<TempoChangeDto>,
+    #[serde(default)]
+    time_signatures: Vec<TimeSignatureDto>,
+    #[serde(default)]
+    swing: SwingDto,
+    #[serde(default = "default_resolution")]
+    resolution: usize,
+    instruments: Vec<InstrumentRepr>,
+    #[serde(default)]
+    buses: Vec<BusDto>,
+    #[serde(default)]
+    master: MasterDto
+}
+
+impl TryFrom<InstrumentPayloadRepr> for InstrumentPayloadDto {
+    type Error = ParseIntError;
+
+    fn try_from(repr: InstrumentPayloadRepr) -> Result<Self, Self::Error> {
+        let resolution = repr.resolution;
+        Ok(Self {
+            tempo: repr.tempo,
+            tempo_changes: repr.tempo_changes,
+            time_signatures: repr.time_signatures,
+            swing: repr.swing,
+            resolution,
+            instruments: repr.instruments
+                .into_iter()
+                .map(|instrument| instrument.into_instrument(resolution))
+                .collect::<Result<_, _>>()?,
+            buses: repr.buses,
+            master: repr.master
+        })
+    }
+}
+
+/// Point of the tempo map, `beat` counts quarter notes from the start of the song and `curve`
+/// sets how the tempo moves on to the next point.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TempoChangeDto {
+    pub beat: f64,
+    pub tempo: f64,
+    #[serde(default)]
+    pub curve: TempoCurve
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TempoCurve {
+    /// Keeps the tempo until the next point.
+    #[default]
+    Step,
+    /// Accelerates or slows down steadily, reaching the tempo of the next point on its beat.
+    Ramp
+}
+
+/// Time signature taking effect at the start of `bar`, bars are counted from zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignatureDto {
+    pub bar: usize,
+    pub numerator: usize,
+    pub denominator: usize
+}
+
+/// Delays every second `division` note (8 for eighths, 16 for sixteenths). An `amount` of 0
+/// plays straight, about 0.67 gives a triplet feel and 1 delays the off-beats by half a note.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SwingDto {
+    pub amount: f32,
+    #[serde(default = "default_swing_division")]
+    pub division: usize
 }
 
+impl Default for SwingDto {
+    fn default() -> Self {
+        Self { amount: 0.0, division: default_swing_division() }
+    }
+}
+
+fn default_swing_division() -> usize {
+    8
+}
+
+/// Bar layout of a song, built from its time signature events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Meter {
+    // The first bar and first tick of every signature.
+    sections: Vec<(usize, usize, TimeSignatureDto)>,
+    resolution: usize
+}
+
+impl Meter {
+    const COMMON_TIME: TimeSignatureDto = TimeSignatureDto { bar: 0, numerator: 4, denominator: 4 };
+
+    pub fn new(signatures: &[TimeSignatureDto], resolution: usize) -> Self {
+        let mut signatures = signatures.to_vec();
+        signatures.sort_by_key(|signature| signature.bar);
+
+        let mut sections = vec![(0, 0, Self::COMMON_TIME)];
+        for signature in signatures {
+            let signature = TimeSignatureDto {
+                numerator: signature.numerator.max(1),
+                denominator: signature.denominator.max(1),
+                ..signature
+            };
+            let (bar, tick, current) = sections[sections.len() - 1];
+            if signature.bar == bar {
+                sections.pop();
+            }
+            let tick = tick + (signature.bar - bar) * Self::bar_length(&current, resolution);
+            sections.push((signature.bar, tick, signature));
+        }
+
+        Self { sections, resolution }
+    }
+
+    fn bar_length(signature: &TimeSignatureDto, resolution: usize) -> usize {
+        signature.numerator * resolution * 4 / signature.denominator
+    }
+
+    pub fn tick_at_bar(&self, bar: usize) -> usize {
+        let (first_bar, tick, signature) = self.sections.iter().rev()
+            .find(|(first_bar, _, _)| *first_bar <= bar)
+            .unwrap_or(&self.sections[0]);
+        tick + (bar - first_bar) * Self::bar_length(signature, self.resolution)
+    }
+
+    /// Bar and beat holding `tick`, both counted from zero. Beats follow the denominator of the
+    /// signature, a beat of 6/8 is an eighth note.
+    pub fn position_at(&self, tick: usize) -> (usize, usize) {
+        let (first_bar, first_tick, signature) = self.sections.iter().rev()
+            .find(|(_, first_tick, _)| *first_tick <= tick)
+            .unwrap_or(&self.sections[0]);
+        let bar_length = Self::bar_length(signature, self.resolution);
+        let beat_length = (bar_length / signature.numerator).max(1);
+        let offset = tick - first_tick;
+        (first_bar + offset / bar_length, offset % bar_length / beat_length)
+    }
+}
+
+/// Group or return bus of the mixer. Instruments reach it through their `output` or their
+/// sends, and it feeds the bus named by `output`, or the master when there is none.
+/// Gain is given in the units of [`InstrumentDto::gain`].
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BusDto {
+    pub name: String,
+    #[serde(default)]
+    pub gain: f32,
+    #[serde(default)]
+    pub pan: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<EffectDto>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>
+}
+
+/// Copies the instrument output to a bus at `level`, from 0 to 1. Pre-fader sends are taken
+/// before the instrument gain and pan.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendDto {
+    pub bus: String,
+    pub level: f32,
+    #[serde(default)]
+    pub pre_fader: bool
+}
+
+/// Processing of the summed instruments. The limiter keeps peaks below `ceiling` in dBFS.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MasterDto {
+    #[serde(default)]
+    pub compressor: Option<CompressorDto>,
+    #[serde(default = "default_ceiling")]
+    pub ceiling: f32
+}
+
+fn default_ceiling() -> f32 {
+    -1.0
+}
+
+impl Default for MasterDto {
+    fn default() -> Self {
+        Self {
+            compressor: None,
+            ceiling: default_ceiling()
+        }
+    }
+}
+
+/// Threshold and makeup are given in dB, attack and release in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CompressorDto {
+    pub threshold: f32,
+    pub ratio: f32,
+    pub attack: f32,
+    pub release: f32,
+    #[serde(default)]
+    pub makeup: f32
+}
+
+/// Messages sent by the client over the streaming socket. A session has to be opened
+/// with the client sample rate before anything can be played.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamRequestDto {
+    Open {
+        sample_rate: u32
+    },
+    /// Replaces the playback in progress, if any. `start` is a tick of the payload resolution.
+    Play {
+        payload: InstrumentPayloadDto,
+        #[serde(default)]
+        start: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        loop_region: Option<LoopRegionDto>
+    },
+    /// Ends the playback in progress, a looping playback only ends this way.
+    Stop
+}
+
+/// Ticks of the payload resolution played over and over, `end` excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopRegionDto {
+    pub start: usize,
+    pub end: usize
+}
+
+/// Offline render of a payload, or of a stored track, into a WAV file. The tail of the
+/// effects after the last note is only rendered when `tail` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderRequestDto {
+    #[serde(flatten)]
+    pub source: RenderSourceDto,
+    #[serde(default = "default_render_sample_rate")]
+    pub sample_rate: u32,
+    #[serde(default)]
+    pub format: WavFormatDto,
+    /// Scales the mix so its loudest sample sits just below full scale.
+    #[serde(default)]
+    pub normalize: bool,
+    #[serde(default)]
+    pub tail: bool
+}
+
+fn default_render_sample_rate() -> u32 {
+    44100
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RenderSourceDto {
+    Payload {
+        payload: InstrumentPayloadDto
+    },
+    /// Name of a track stored with the tracks endpoint.
+    Track {
+        track: String
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WavFormatDto {
+    Int16,
+    #[default]
+    Int24,
+    Float32
+}
+
+/// Sent by the server as text messages next to the binary sound packets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEventDto {
+    /// The request could not be handled.
+    Error {
+        message: String
+    },
+    /// The last chunk had `overs` samples above 0 dBFS, peaking at `true_peak` dBTP.
+    Clipping {
+        true_peak: f32,
+        overs: usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "InstrumentRepr")]
 pub struct InstrumentDto {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instrument_type: Option<String>,
     pub gain: f32,
-    pub notes: HashMap<usize, Vec<MidiKey>>
+    /// Stereo position from -1 (left) to 1 (right).
+    #[serde(default)]
+    pub pan: f32,
+    pub notes: Vec<NoteDto>,
+    #[serde(default)]
+    pub envelope: EnvelopeDto,
+    #[serde(default)]
+    pub polyphony: PolyphonyDto,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<FilterDto>,
+    /// Processes the summed voices, in order. Always written, payloads without it predate the
+    /// effect chain and get the reverb their sine and square instruments used to play with.
+    #[serde(default)]
+    pub effects: Vec<EffectDto>,
+    /// Name of the bus the instrument plays into, the master when there is none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sends: Vec<SendDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modulation_routings: Vec<ModulationRoutingDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub automation: Vec<AutomationLaneDto>,
+    /// Overrides the swing of the payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swing: Option<SwingDto>,
+    /// Settings specific to the instrument type, see for example [`SamplerParametersDto`].
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub parameters: serde_json::Value
+}
+
+#[derive(Deserialize)]
+struct InstrumentRepr {
+    name: String,
+    #[serde(default)]
+    instrument_type: Option<String>,
+    gain: f32,
+    #[serde(default)]
+    pan: f32,
+    notes: NotesRepr,
+    #[serde(default)]
+    envelope: EnvelopeDto,
+    #[serde(default)]
+    polyphony: PolyphonyDto,
+    #[serde(default)]
+    filter: Option<FilterDto>,
+    #[serde(default)]
+    effects: Option<Vec<EffectDto>>,
+    #[serde(default)]
+    output: Option<String>,
+    #[serde(default)]
+    sends: Vec<SendDto>,
+    #[serde(default)]
+    modulation_routings: Vec<ModulationRoutingDto>,
+    #[serde(default)]
+    automation: Vec<AutomationLaneDto>,
+    #[serde(default)]
+    swing: Option<SwingDto>,
+    #[serde(default)]
+    parameters: serde_json::Value
+}
+
+impl TryFrom<InstrumentRepr> for InstrumentDto {
+    type Error = ParseIntError;
+
+    /// On its own, an instrument with notes keyed by beat is read at the default resolution.
+    fn try_from(repr: InstrumentRepr) -> Result<Self, Self::Error> {
+        repr.into_instrument(TICKS_PER_BEAT)
+    }
+}
+
+impl InstrumentRepr {
+    fn into_instrument(self, resolution: usize) -> Result<InstrumentDto, ParseIntError> {
+        let effects = self.effects.unwrap_or_else(|| {
+            // The first payloads had no effects, sine and square voices were always reverberated.
+            let instrument_type = self.instrument_type.as_deref().unwrap_or(&self.name);
+            if ["sine", "square"].contains(&instrument_type) {
+                vec![EffectDto::Reverb(LEGACY_REVERB)]
+            } else {
+                Vec::new()
+            }
+        });
+
+        Ok(InstrumentDto {
+            name: self.name,
+            instrument_type: self.instrument_type,
+            gain: self.gain,
+            pan: self.pan,
+            notes: self.notes.into_notes(resolution)?,
+            envelope: self.envelope,
+            polyphony: self.polyphony,
+            filter: self.filter,
+            effects,
+            output: self.output,
+            sends: self.sends,
+            modulation_routings: self.modulation_routings,
+            automation: self.automation,
+            swing: self.swing,
+            parameters: self.parameters
+        })
+    }
+}
+
+impl InstrumentDto {
+    /// Older payloads selected the instrument by its display name.
+    pub fn instrument_type(&self) -> &str {
+        self.instrument_type.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn parameters<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_value(self.parameters.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplerParametersDto {
+    pub zones: Vec<SampleZoneDto>,
+    /// Plays every sample to its end regardless of the note length, as drum kits expect.
+    #[serde(default)]
+    pub one_shot: bool
+}
+
+/// A WAV file played for the keys between `low` and `high`, at its original pitch on `root`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampleZoneDto {
+    pub path: String,
+    pub root: MidiKey,
+    #[serde(default)]
+    pub low: Option<MidiKey>,
+    #[serde(default)]
+    pub high: Option<MidiKey>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FmParametersDto {
+    pub operators: Vec<FmOperatorDto>
+}
+
+/// A sine operator running at `ratio` times the note frequency. Its output is scaled by `index`,
+/// which is the phase deviation in radians when it modulates another operator and the output
+/// level when it is a carrier. Operators can only modulate operators listed before them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FmOperatorDto {
+    pub ratio: f32,
+    pub index: f32,
+    /// Falls back to the instrument envelope.
+    #[serde(default)]
+    pub envelope: Option<EnvelopeDto>,
+    /// The operator this one modulates, or none for a carrier.
+    #[serde(default)]
+    pub modulates: Option<usize>
+}
+
+/// Pieces of the drum kit, laid out on the General MIDI percussion keys. The open hat is choked
+/// by the closed one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DrumKitParametersDto {
+    pub kick: DrumPieceDto,
+    pub rimshot: DrumPieceDto,
+    pub snare: DrumPieceDto,
+    pub clap: DrumPieceDto,
+    pub closed_hat: DrumPieceDto,
+    pub open_hat: DrumPieceDto,
+    pub low_tom: DrumPieceDto,
+    pub mid_tom: DrumPieceDto,
+    pub high_tom: DrumPieceDto
+}
+
+impl Default for DrumKitParametersDto {
+    fn default() -> Self {
+        let tom = |tone| DrumPieceDto {
+            tone,
+            sweep: 1.5,
+            sweep_time: 0.05,
+            body: 0.9,
+            body_decay: 0.35,
+            noise: 0.1,
+            noise_decay: 0.05,
+            noise_cutoff: 1000.0,
+            bursts: 1
+        };
+        let hat = |noise_decay| DrumPieceDto {
+            tone: 0.0,
+            sweep: 1.0,
+            sweep_time: 0.0,
+            body: 0.0,
+            body_decay: 0.0,
+            noise: 0.6,
+            noise_decay,
+            noise_cutoff: 7000.0,
+            bursts: 1
+        };
+
+        Self {
+            kick: DrumPieceDto::default(),
+            rimshot: DrumPieceDto {
+                tone: 500.0,
+                sweep: 1.0,
+                sweep_time: 0.0,
+                body: 0.6,
+                body_decay: 0.03,
+                noise: 0.3,
+                noise_decay: 0.02,
+                noise_cutoff: 3000.0,
+                bursts: 1
+            },
+            snare: DrumPieceDto {
+                tone: 185.0,
+                sweep: 1.5,
+                sweep_time: 0.01,
+                body: 0.5,
+                body_decay: 0.1,
+                noise: 0.8,
+                noise_decay: 0.15,
+                noise_cutoff: 1500.0,
+                bursts: 1
+            },
+            clap: DrumPieceDto {
+                tone: 0.0,
+                sweep: 1.0,
+                sweep_time: 0.0,
+                body: 0.0,
+                body_decay: 0.0,
+                noise: 0.8,
+                noise_decay: 0.15,
+                noise_cutoff: 1000.0,
+                bursts: 4
+            },
+            closed_hat: hat(0.04),
+            open_hat: hat(0.4),
+            low_tom: tom(90.0),
+            mid_tom: tom(130.0),
+            high_tom: tom(180.0)
+        }
+    }
+}
+
+/// A drum hit made of a tuned sine body and highpassed noise. The body starts at `sweep` times
+/// `tone` and falls to `tone` over `sweep_time`. Times are exponential time constants in seconds.
+/// Noise is repeated in `bursts` a few milliseconds apart, as for a clap.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrumPieceDto {
+    pub tone: f32,
+    pub sweep: f32,
+    pub sweep_time: f32,
+    pub body: f32,
+    pub body_decay: f32,
+    pub noise: f32,
+    pub noise_decay: f32,
+    pub noise_cutoff: f32,
+    #[serde(default = "default_bursts")]
+    pub bursts: usize
+}
+
+fn default_bursts() -> usize {
+    1
+}
+
+impl Default for DrumPieceDto {
+    fn default() -> Self {
+        Self {
+            tone: 50.0,
+            sweep: 6.0,
+            sweep_time: 0.03,
+            body: 1.0,
+            body_decay: 0.3,
+            noise: 0.05,
+            noise_decay: 0.01,
+            noise_cutoff: 2000.0,
+            bursts: 1
+        }
+    }
+}
+
+/// Note start and length are given in ticks of the payload resolution, velocity from 0 to 127.
+/// The start counts from the beginning of `bar` when one is given, from the song start otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteDto {
+    pub key: MidiKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bar: Option<usize>,
+    pub start: usize,
+    pub length: usize,
+    #[serde(default = "default_velocity")]
+    pub velocity: u8
+}
+
+impl NoteDto {
+    /// Start of the note in ticks from the song start.
+    pub fn start_tick(&self, meter: &Meter) -> usize {
+        self.bar.map_or(0, |bar| meter.tick_at_bar(bar)) + self.start
+    }
+}
+
+fn default_velocity() -> u8 {
+    MAX_VELOCITY
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NotesRepr {
+    Notes(Vec<NoteDto>),
+    // Older payloads keyed the notes by beat, with every note lasting one beat.
+    Beats(HashMap<String, Vec<MidiKey>>)
+}
+
+impl NotesRepr {
+    /// `resolution` is the one of the payload, beats are converted to its ticks.
+    fn into_notes(self, resolution: usize) -> Result<Vec<NoteDto>, ParseIntError> {
+        match self {
+            NotesRepr::Notes(notes) => Ok(notes),
+            NotesRepr::Beats(beats) => {
+                let mut notes = Vec::new();
+                for (beat, keys) in beats {
+                    let beat = beat.parse::<usize>()?;
+                    notes.extend(keys.into_iter().map(|key| NoteDto {
+                        key,
+                        bar: None,
+                        start: beat * resolution,
+                        length: resolution,
+                        velocity: MAX_VELOCITY
+                    }));
+                }
+                notes.sort_by_key(|note| (note.start, note.key));
+                Ok(notes)
+            }
+        }
+    }
+}
+
+/// Attack, decay and release are given in seconds, sustain as a level between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EnvelopeDto {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    #[serde(default)]
+    pub curve: EnvelopeCurve
+}
+
+impl Default for EnvelopeDto {
+    fn default() -> Self {
+        Self {
+            attack: 0.01,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.01,
+            curve: EnvelopeCurve::Linear
+        }
+    }
+}
+
+/// Filter applied to every voice of an instrument. The envelope, which falls back to the
+/// instrument envelope, moves the cutoff by up to `envelope_amount` octaves.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FilterDto {
+    pub mode: FilterMode,
+    pub cutoff: f32,
+    #[serde(default = "default_resonance")]
+    pub resonance: f32,
+    /// Boost or cut of the shelf modes in decibels.
+    #[serde(default)]
+    pub gain: f32,
+    #[serde(default)]
+    pub envelope: Option<EnvelopeDto>,
+    #[serde(default)]
+    pub envelope_amount: f32
+}
+
+fn default_resonance() -> f32 {
+    std::f32::consts::FRAC_1_SQRT_2
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    LowShelf,
+    HighShelf
+}
+
+/// One effect of an instrument chain, selected by its `effect` field.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "effect", rename_all = "snake_case")]
+pub enum EffectDto {
+    Distortion(DistortionDto),
+    Modulation(ModulationDto),
+    Delay(DelayDto),
+    Reverb(ReverbDto)
+}
+
+/// Room size, damping and mix range from 0 to 1, pre-delay is given in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReverbDto {
+    pub room_size: f32,
+    pub damping: f32,
+    #[serde(default)]
+    pub pre_delay: f32,
+    pub mix: f32
+}
+
+/// Close to the echoes at 0.1, 0.2 and 0.3 seconds that used to follow every sine and square voice.
+pub const LEGACY_REVERB: ReverbDto = ReverbDto {
+    room_size: 0.5,
+    damping: 0.5,
+    pre_delay: 0.1,
+    mix: 0.3
+};
+
+/// Echo repeating every `division` note (4 for quarter notes, 8 for eighths), following the tempo map.
+/// The optional cutoffs filter the feedback path, so every repeat gets darker or thinner.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DelayDto {
+    pub division: u32,
+    #[serde(default)]
+    pub feel: NoteFeel,
+    pub feedback: f32,
+    #[serde(default)]
+    pub lowpass: Option<f32>,
+    #[serde(default)]
+    pub highpass: Option<f32>,
+    /// Bounces the repeats between the left and right channel.
+    #[serde(default)]
+    pub ping_pong: bool,
+    pub mix: f32
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteFeel {
+    #[default]
+    Straight,
+    Dotted,
+    Triplet
+}
+
+/// Effects swept by a sine LFO running at `rate` Hz, with `depth` and `mix` from 0 to 1.
+/// Feedback ranges from -1 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModulationDto {
+    Chorus {
+        rate: f32,
+        depth: f32,
+        mix: f32
+    },
+    Flanger {
+        rate: f32,
+        depth: f32,
+        #[serde(default)]
+        feedback: f32,
+        mix: f32
+    },
+    Phaser {
+        rate: f32,
+        depth: f32,
+        #[serde(default = "default_phaser_stages")]
+        stages: usize,
+        #[serde(default)]
+        feedback: f32,
+        mix: f32
+    }
+}
+
+fn default_phaser_stages() -> usize {
+    4
+}
+
+/// Non-linear shaping with `mix` from 0 to 1. The shaper runs `oversampling` times faster
+/// than the engine (up to 8) so the harmonics it adds alias less.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DistortionDto {
+    #[serde(flatten)]
+    pub shape: DistortionShape,
+    #[serde(default = "default_oversampling")]
+    pub oversampling: usize,
+    pub mix: f32
+}
+
+fn default_oversampling() -> usize {
+    1
+}
+
+/// `drive` is the gain in dB applied before shaping.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DistortionShape {
+    /// Tanh soft clipping.
+    Drive {
+        drive: f32
+    },
+    /// Soft clipping biased by `asymmetry` (-1 to 1), adding even harmonics.
+    Waveshaper {
+        drive: f32,
+        asymmetry: f32
+    },
+    /// Folds anything above `threshold` back into range.
+    Foldback {
+        drive: f32,
+        threshold: f32
+    },
+    /// Quantizes to `bits` and holds every sample for `downsample` samples.
+    Bitcrusher {
+        bits: u32,
+        #[serde(default = "default_downsample")]
+        downsample: usize
+    }
+}
+
+fn default_downsample() -> usize {
+    1
+}
+
+/// Breakpoints for one parameter over the song. Before the first and after the last point the
+/// parameter keeps the value of that point.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutomationLaneDto {
+    pub parameter: AutomationParameter,
+    pub points: Vec<AutomationPointDto>
+}
+
+/// `tick` is given in the payload resolution, `curve` sets how the value moves on to the next point.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AutomationPointDto {
+    pub tick: usize,
+    pub value: f32,
+    #[serde(default)]
+    pub curve: AutomationCurve
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutomationCurve {
+    #[default]
+    Linear,
+    /// Constant ratio steps, even sounding sweeps for frequencies. Falls back to linear
+    /// when the values are not both positive.
+    Exponential,
+    /// Keeps the value until the next point.
+    Step
+}
+
+/// Gain is given in the units of [`InstrumentDto::gain`], the cutoff in Hz, mixes from 0 to 1
+/// and the delay time in beats. Automated gain and pan act on the instrument output, after its effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutomationParameter {
+    Gain,
+    Pan,
+    Cutoff,
+    DistortionMix,
+    ModulationMix,
+    DelayMix,
+    ReverbMix,
+    DelayTime
+}
+
+/// Sends a modulation source to a voice or effect parameter. `depth` is in semitones for the pitch,
+/// in octaves for the filter cutoff, in beats for the delay time and in the parameter range for
+/// the others.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModulationRoutingDto {
+    pub source: ModulationSourceDto,
+    pub destination: ModulationDestination,
+    pub depth: f32
+}
+
+/// LFOs swing from -1 to 1, envelopes and velocity range from 0 to 1. Every note starts
+/// its own sources, an LFO starting at `phase` given as a fraction of a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModulationSourceDto {
+    Lfo {
+        #[serde(default)]
+        shape: LfoShape,
+        rate: f32,
+        #[serde(default)]
+        phase: f32
+    },
+    Envelope {
+        envelope: EnvelopeDto
+    },
+    Velocity
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LfoShape {
+    #[default]
+    Sine,
+    Triangle,
+    Square,
+    Sawtooth
+}
+
+/// Gain is scaled down by up to `depth` as its source falls to its lowest value, the other
+/// destinations are offset by the source value times `depth`. The effect parameters are shared
+/// by the voices of an instrument, their sources restart with every note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModulationDestination {
+    Pitch,
+    Gain,
+    Pan,
+    Cutoff,
+    DistortionMix,
+    ModulationMix,
+    DelayMix,
+    ReverbMix,
+    DelayTime
+}
+
+impl ModulationDestination {
+    /// The parameter of the instrument effect chain, `None` for the voice parameters.
+    pub fn effect_parameter(&self) -> Option<AutomationParameter> {
+        match self {
+            ModulationDestination::Pitch
+            | ModulationDestination::Gain
+            | ModulationDestination::Pan
+            | ModulationDestination::Cutoff => None,
+            ModulationDestination::DistortionMix => Some(AutomationParameter::DistortionMix),
+            ModulationDestination::ModulationMix => Some(AutomationParameter::ModulationMix),
+            ModulationDestination::DelayMix => Some(AutomationParameter::DelayMix),
+            ModulationDestination::ReverbMix => Some(AutomationParameter::ReverbMix),
+            ModulationDestination::DelayTime => Some(AutomationParameter::DelayTime)
+        }
+    }
+}
+
+/// How many notes of an instrument can sound at once, and which one makes room for a new note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolyphonyDto {
+    pub max_voices: usize,
+    #[serde(default)]
+    pub stealing: VoiceStealing
+}
+
+impl Default for PolyphonyDto {
+    fn default() -> Self {
+        Self {
+            max_voices: 16,
+            stealing: VoiceStealing::Oldest
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceStealing {
+    #[default]
+    Oldest,
+    Quietest,
+    /// Retriggers a voice already playing the same key, falling back to the oldest voice.
+    SameNote
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvelopeCurve {
+    #[default]
+    Linear,
+    Exponential
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum SoundOutputPacket {
     End {
-        length: u16,
+        length: u32,
         channel_data: Option<ChannelData>
     },
     Data {
@@ -58,7 +1040,7 @@ impl TryFrom<(Vec<u8>, usize)> for SoundOutputPacket {
         match tag_byte {
             0x00 => {
                 let length = data_iterator.next_tuple()
-                .map(|(b0, b1)| u16::from_le_bytes([b0, b1]))
+                .map(|(b0, b1, b2, b3)| u32::from_le_bytes([b0, b1, b2, b3]))
                 .unwrap();
 
                 let channel_data = if length != 0 {
@@ -223,4 +1205,131 @@ mod test {
         assert_eq!(ChannelData::try_from((bytes, 44100))?, channel_data);
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sound_output_packet_end_longer_than_u16() -> Result<(), String> {
+        // given
+        let packet = SoundOutputPacket::End {
+            length: 96000,
+            channel_data: Some(ChannelData::Stereo(vec![1.0; 96000], vec![-1.0; 96000]))
+        };
+
+        // when
+        let bytes = Vec::<u8>::from(packet.clone());
+
+        // then
+        assert_eq!(SoundOutputPacket::try_from((bytes, 192000))?, packet);
+        Ok(())
+    }
+
+    #[test]
+    fn test_instrument_legacy_notes() -> Result<(), serde_json::Error> {
+        // given
+        let payload = r#"{"name": "sine", "gain": 0.0, "notes": {"2": ["A4", "C4"], "0": ["E4"]}}"#;
+
+        // when
+        let instrument = serde_json::from_str::<InstrumentDto>(payload)?;
+
+        // then
+        assert_eq!(instrument.notes, vec![
+            NoteDto { key: MidiKey::E4, bar: None, start: 0, length: TICKS_PER_BEAT, velocity: MAX_VELOCITY },
+            NoteDto { key: MidiKey::A4, bar: None, start: 2 * TICKS_PER_BEAT, length: TICKS_PER_BEAT, velocity: MAX_VELOCITY },
+            NoteDto { key: MidiKey::C4, bar: None, start: 2 * TICKS_PER_BEAT, length: TICKS_PER_BEAT, velocity: MAX_VELOCITY },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_notes_follow_payload_resolution() -> Result<(), serde_json::Error> {
+        // given
+        let payload = r#"{"tempo": 120, "resolution": 480, "instruments": [
+            {"name": "sine", "gain": 0.0, "notes": {"1": ["A4"]}}
+        ]}"#;
+
+        // when
+        let payload = serde_json::from_str::<InstrumentPayloadDto>(payload)?;
+
+        // then
+        assert_eq!(payload.instruments[0].notes, vec![
+            NoteDto { key: MidiKey::A4, bar: None, start: 480, length: 480, velocity: MAX_VELOCITY },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_instrument_effect_chain_keeps_order() -> Result<(), serde_json::Error> {
+        // given
+        let payload = r#"{"name": "sawtooth", "gain": 0.0, "notes": [], "effects": [
+            {"effect": "reverb", "room_size": 0.5, "damping": 0.5, "mix": 0.3},
+            {"effect": "modulation", "type": "chorus", "rate": 1.0, "depth": 0.5, "mix": 0.5},
+            {"effect": "distortion", "type": "drive", "drive": 12.0, "mix": 1.0}
+        ]}"#;
+
+        // when
+        let instrument = serde_json::from_str::<InstrumentDto>(payload)?;
+
+        // then
+        assert_eq!(instrument.effects, vec![
+            EffectDto::Reverb(ReverbDto { room_size: 0.5, damping: 0.5, pre_delay: 0.0, mix: 0.3 }),
+            EffectDto::Modulation(ModulationDto::Chorus { rate: 1.0, depth: 0.5, mix: 0.5 }),
+            EffectDto::Distortion(DistortionDto { shape: DistortionShape::Drive { drive: 12.0 }, oversampling: 1, mix: 1.0 }),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_payloads_keep_their_reverb() -> Result<(), serde_json::Error> {
+        // given
+        let instrument = |name: &str, effects: &str| {
+            serde_json::from_str::<InstrumentDto>(&format!(r#"{{"name": "{name}", "gain": 0.0, "notes": []{effects}}}"#))
+        };
+
+        // when
+        let (sine, square, sawtooth) = (instrument("sine", "")?, instrument("square", "")?, instrument("sawtooth", "")?);
+        let dry_sine = instrument("sine", r#", "effects": []"#)?;
+
+        // then
+        assert_eq!(sine.effects, vec![EffectDto::Reverb(LEGACY_REVERB)]);
+        assert_eq!(square.effects, vec![EffectDto::Reverb(LEGACY_REVERB)]);
+        assert!(sawtooth.effects.is_empty());
+        assert!(dry_sine.effects.is_empty());
+        assert!(serde_json::to_string(&dry_sine)?.contains(r#""effects":[]"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_meter_follows_time_signature_changes() {
+        // given
+        let meter = Meter::new(&[
+            TimeSignatureDto { bar: 2, numerator: 6, denominator: 8 },
+            TimeSignatureDto { bar: 3, numerator: 3, denominator: 4 }
+        ], TICKS_PER_BEAT);
+
+        // when
+        let bars = [1, 2, 3, 4].map(|bar| meter.tick_at_bar(bar) / (TICKS_PER_BEAT / 2));
+        let position = meter.position_at(meter.tick_at_bar(2) + 5 * TICKS_PER_BEAT / 2);
+
+        // then
+        assert_eq!(bars, [8, 16, 22, 28]);
+        assert_eq!(position, (2, 5));
+    }
+
+    #[test]
+    fn test_render_request_of_stored_track() -> Result<(), serde_json::Error> {
+        // given
+        let request = r#"{"track": "default", "format": "float32", "tail": true}"#;
+
+        // when
+        let request = serde_json::from_str::<RenderRequestDto>(request)?;
+
+        // then
+        assert_eq!(request, RenderRequestDto {
+            source: RenderSourceDto::Track { track: "default".to_string() },
+            sample_rate: 44100,
+            format: WavFormatDto::Float32,
+            normalize: false,
+            tail: true
+        });
+        Ok(())
+    }
+}